@@ -0,0 +1,39 @@
+use std::process::Command;
+
+/// Embeds what `version --verbose` prints that Cargo doesn't provide itself
+fn main() {
+    // Only known when building from a git checkout
+    let commit = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|commit| commit.trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=GIT_COMMIT={}", commit);
+
+    let lock = std::fs::read_to_string("Cargo.lock").unwrap_or_default();
+    for name in ["zbus", "reqwest"] {
+        println!(
+            "cargo:rustc-env={}_VERSION={}",
+            name.to_uppercase(),
+            locked_version(&lock, name).unwrap_or("unknown")
+        );
+    }
+
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+    println!("cargo:rerun-if-changed=Cargo.lock");
+}
+
+/// Finds the version of a package in Cargo.lock, without pulling in a TOML parser
+fn locked_version<'a>(lock: &'a str, name: &str) -> Option<&'a str> {
+    let package = format!("name = \"{}\"\n", name);
+    let start = lock.find(&package)? + package.len();
+    lock[start..]
+        .lines()
+        .next()?
+        .strip_prefix("version = \"")?
+        .strip_suffix('"')
+}
@@ -0,0 +1,63 @@
+use std::{
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{search::Track, Error};
+
+/// How long the songs of a `--list` search can be picked from with `play-song last`
+const TTL: Duration = Duration::from_secs(10 * 60);
+
+/// The songs that were shown by the last `play-song search --list`
+#[derive(Serialize, Deserialize)]
+struct LastSearch {
+    /// When the search was done, in seconds since the epoch
+    saved: u64,
+    tracks: Vec<Track>,
+}
+
+/// Where the last search is kept, usually `~/.cache/spotify-control/last-search.json`
+fn path() -> Option<PathBuf> {
+    dirs::cache_dir().map(|dir| dir.join("spotify-control").join("last-search.json"))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Remembers the songs of a search for `play-song last`
+pub fn save(tracks: &[Track]) -> Result<(), Error> {
+    let path = path().ok_or(Error::NoCacheDir)?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let last = LastSearch {
+        saved: now(),
+        tracks: tracks.to_vec(),
+    };
+    std::fs::write(path, serde_json::to_string(&last).unwrap())?;
+    Ok(())
+}
+
+/// Forgets the last search, so `play-song last` can't pick from results of an older one
+pub fn clear() {
+    if let Some(path) = path() {
+        // There's nothing to clear when it doesn't exist
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+/// The songs of the last search, unless it's been longer than [`TTL`]
+pub fn load() -> Result<Vec<Track>, Error> {
+    let last = path()
+        .and_then(|path| std::fs::read_to_string(path).ok())
+        .and_then(|json| serde_json::from_str::<LastSearch>(&json).ok())
+        .filter(|last| now().saturating_sub(last.saved) <= TTL.as_secs())
+        .filter(|last| !last.tracks.is_empty());
+    last.map(|last| last.tracks).ok_or(Error::NoLastSearch)
+}
@@ -0,0 +1,348 @@
+use std::{path::PathBuf, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "search")]
+use crate::search::SearchBackend;
+use crate::{display::DEFAULT_TRIM_PATTERNS, Error};
+
+/// Environment variable that can be used to change the service instead of `--service-name`
+const SERVICE_ENV: &str = "SPOTIFY_CONTROL_SERVICE";
+
+/// Written by `config init`, keep this in sync with the fields of [`Config`]
+const TEMPLATE: &str = r#"# Configuration for spotify-control
+# Every key is optional. The values shown here are the defaults, except where
+# it says "For example", since those keys aren't set by default.
+
+# The DBus service to send the commands to. When not set, Spotify is used,
+# or the first other MPRIS player if Spotify isn't running. For example:
+# service_name = "org.mpris.MediaPlayer2.spotify"
+
+# How many songs `play-song search --list` shows.
+# count = 5
+
+# Remove clutter like "(feat. X)" and "- Remastered 2011" from the names that
+# are shown, the same as `--trim-feat`.
+# trim_feat = false
+
+# The regexes that are removed from names when trim_feat is on.
+# trim_patterns = [
+#     '(?i)\s*[(\[](feat\.|ft\.|featuring|with) [^)\]]*[)\]]',
+#     '(?i)\s+-\s+(\d{4}\s+)?remaster(ed)?(\s+\d{4})?(\s+version)?$',
+# ]
+
+# Only show this many artists followed by "+N more", the same as `--max-artists`.
+# All artists are shown when it's not set. For example:
+# max_artists = 2
+
+# How often to try connecting to the bus again when it fails, which can happen
+# when this runs right at login. The same as `--retries`.
+# retries = 2
+
+# How often to try reading the song again when it fails, which can happen right
+# after the song changes.
+# metadata_retries = 2
+
+# How many seconds to wait for a response before giving up.
+# timeout = 10
+
+# These override timeout for talking to the player, searching and getting the
+# artwork, they're the same as timeout when not set. For example:
+# dbus_timeout = 10
+# search_timeout = 10
+# artwork_timeout = 10
+
+# The preferred size of the artwork in pixels. Spotify has artwork of 64, 300
+# and 640 pixels, the closest one is used. When not set the artwork Spotify
+# reports is used, as is the artwork of other players. For example:
+# artwork_size = 300
+
+# Artwork larger than this many bytes isn't downloaded, the notification is
+# shown without it. The same as `--artwork-max-bytes`.
+# artwork_max_bytes = 5242880
+
+# Where searches are sent to, "heroku" or "spotify" (the Web API), the same as
+# `--search-backend`. When not set the Web API is used if client_id and
+# client_secret are set, and heroku otherwise. For example:
+# search_backend = "heroku"
+
+# Let `watch` pause the music when the screen locks, and start it again when it
+# unlocks if it was playing before.
+# pause_on_lock = false
+
+# Credentials for the Spotify Web API, create an app on
+# https://developer.spotify.com/dashboard to get them.
+# client_id = ""
+# client_secret = ""
+
+# Lets the Web API act for you, which `play --device` needs. Run
+# `spotify-control auth` to get one.
+# refresh_token = ""
+
+# The Spotify Connect device `play --device default` plays on. For example:
+# default_device = "Living Room"
+
+# The command that runs when spotify-control is started without one, with its
+# flags and quoted like in a shell. Flags that go before the command, like
+# --quiet, can't be used here. The help is printed when it's not set. For
+# example:
+# default_command = "play-pause"
+"#;
+
+/// What is being waited for, each has a timeout in the config file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Timeout {
+    /// Connecting to the player and reading from it
+    Dbus,
+    Search,
+    Artwork,
+    /// The rest of the Spotify Web API
+    Web,
+}
+
+impl Timeout {
+    pub const ALL: [Timeout; 4] = [
+        Timeout::Dbus,
+        Timeout::Search,
+        Timeout::Artwork,
+        Timeout::Web,
+    ];
+}
+
+/// Settings that can be set in the config file, the environment or with flags.
+/// Each of those overrides the ones before it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// The DBus service to send the commands to, detected when not set
+    pub service_name: Option<String>,
+    /// How many songs `play-song search --list` shows
+    pub count: usize,
+    /// Whether names are cleaned up with `trim_patterns` before they're shown
+    pub trim_feat: bool,
+    /// Regexes that are removed from names when `trim_feat` is set
+    pub trim_patterns: Vec<String>,
+    /// Only this many artists are shown when set, all of them are shown otherwise
+    pub max_artists: Option<usize>,
+    /// How often to try connecting to the bus again when it fails
+    pub retries: u32,
+    /// How often to try reading the metadata again when it fails
+    pub metadata_retries: u32,
+    /// In seconds, for everything that doesn't have a timeout of its own
+    pub timeout: f64,
+    pub dbus_timeout: Option<f64>,
+    pub search_timeout: Option<f64>,
+    pub artwork_timeout: Option<f64>,
+    /// The preferred size of Spotify artwork in pixels, the size Spotify reports is used otherwise
+    pub artwork_size: Option<u32>,
+    /// Artwork that's larger is left out of notifications
+    pub artwork_max_bytes: u64,
+    /// Whether `watch` pauses while the screen is locked
+    pub pause_on_lock: bool,
+    /// Where searches are sent to, see [`Config::search_backend`] for the default
+    #[cfg(feature = "search")]
+    pub search_backend: Option<SearchBackend>,
+    /// Credentials for the Spotify Web API, from https://developer.spotify.com/dashboard
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+    /// Lets the Web API act for the user, from `spotify-control auth`
+    pub refresh_token: Option<String>,
+    /// The name of the device `play --device default` plays on
+    pub default_device: Option<String>,
+    /// The command, with flags, that is run when none is given
+    pub default_command: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            service_name: None,
+            count: 5,
+            trim_feat: false,
+            trim_patterns: DEFAULT_TRIM_PATTERNS
+                .iter()
+                .map(|p| p.to_string())
+                .collect(),
+            max_artists: None,
+            retries: 2,
+            metadata_retries: 2,
+            timeout: 10.0,
+            dbus_timeout: None,
+            search_timeout: None,
+            artwork_timeout: None,
+            artwork_size: None,
+            artwork_max_bytes: 5 * 1024 * 1024,
+            pause_on_lock: false,
+            #[cfg(feature = "search")]
+            search_backend: None,
+            client_id: None,
+            client_secret: None,
+            refresh_token: None,
+            default_device: None,
+            default_command: None,
+        }
+    }
+}
+
+impl Config {
+    /// Where the config file is read from, usually `~/.config/spotify-control/config.toml`
+    pub fn path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("spotify-control").join("config.toml"))
+    }
+
+    /// Reads the config file if there is one, and applies the environment on top of it
+    pub fn load() -> Result<Config, Error> {
+        let mut config = match Self::path() {
+            Some(path) if path.exists() => toml::from_str(&std::fs::read_to_string(path)?)?,
+            _ => Config::default(),
+        };
+        if let Ok(service) = std::env::var(SERVICE_ENV) {
+            config.service_name = Some(service);
+        }
+        // An empty list would have nothing to pick from
+        if config.count == 0 {
+            return Err(Error::InvalidCount);
+        }
+        Ok(config)
+    }
+
+    /// Writes a commented default config file, returning where it was written to
+    pub fn init(force: bool) -> Result<PathBuf, Error> {
+        let path = Self::path().ok_or(Error::NoConfigDir)?;
+        if path.exists() && !force {
+            return Err(Error::ConfigExists(path));
+        }
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(&path, TEMPLATE)?;
+        Ok(path)
+    }
+
+    /// A copy that can be shown to the user, without any secrets in it
+    pub fn redacted(&self) -> Config {
+        let mut config = self.clone();
+        for secret in [&mut config.client_secret, &mut config.refresh_token] {
+            if secret.is_some() {
+                *secret = Some("<redacted>".to_string());
+            }
+        }
+        config
+    }
+
+    /// The Web API client id and secret, if both are set. Empty ones count as not set, the
+    /// template has them like that.
+    #[cfg(any(feature = "search", feature = "web-api"))]
+    pub fn credentials(&self) -> Option<(&str, &str)> {
+        Some((non_empty(&self.client_id)?, non_empty(&self.client_secret)?))
+    }
+
+    /// The refresh token for the Web API, if it's set and not empty
+    #[cfg(feature = "web-api")]
+    pub fn refresh_token(&self) -> Option<&str> {
+        non_empty(&self.refresh_token)
+    }
+
+    /// How long to wait for `kind`, the `timeout` when there's no timeout for it in particular
+    pub fn timeout(&self, kind: Timeout) -> Result<Duration, Error> {
+        let specific = match kind {
+            Timeout::Dbus => self.dbus_timeout.map(|t| ("dbus_timeout", t)),
+            Timeout::Search => self.search_timeout.map(|t| ("search_timeout", t)),
+            Timeout::Artwork => self.artwork_timeout.map(|t| ("artwork_timeout", t)),
+            Timeout::Web => None,
+        };
+        let (key, secs) = specific.unwrap_or(("timeout", self.timeout));
+        // try_from_secs_f64 also rejects what doesn't fit in a Duration, like 1e300
+        match Duration::try_from_secs_f64(secs) {
+            Ok(timeout) if !timeout.is_zero() => Ok(timeout),
+            _ => Err(Error::InvalidTimeout(key)),
+        }
+    }
+
+    /// The configured search backend. When none is configured the Web API is used if there are
+    /// credentials for it (and it was built in), and heroku otherwise.
+    #[cfg(feature = "search")]
+    pub fn search_backend(&self) -> Result<SearchBackend, Error> {
+        let web_api = cfg!(feature = "web-api") && self.credentials().is_some();
+        match self.search_backend {
+            Some(SearchBackend::Spotify) if self.credentials().is_none() => {
+                Err(Error::MissingCredentials)
+            }
+            Some(backend) => Ok(backend),
+            None if web_api => Ok(SearchBackend::Spotify),
+            None => Ok(SearchBackend::Heroku),
+        }
+    }
+}
+
+/// The value of a key that can be left empty, like the credentials in the template
+#[cfg(any(feature = "search", feature = "web-api"))]
+fn non_empty(value: &Option<String>) -> Option<&str> {
+    value.as_deref().filter(|value| !value.trim().is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timeout(config: &Config, kind: Timeout) -> Result<Duration, &'static str> {
+        config.timeout(kind).map_err(|e| match e {
+            Error::InvalidTimeout(key) => key,
+            e => panic!("unexpected error {}", e),
+        })
+    }
+
+    #[test]
+    fn specific_timeouts_override_the_global_one() {
+        let config = Config {
+            timeout: 30.0,
+            search_timeout: Some(2.5),
+            ..Config::default()
+        };
+        assert_eq!(timeout(&config, Timeout::Dbus), Ok(Duration::from_secs(30)));
+        assert_eq!(timeout(&config, Timeout::Web), Ok(Duration::from_secs(30)));
+        assert_eq!(
+            timeout(&config, Timeout::Search),
+            Ok(Duration::from_millis(2500))
+        );
+    }
+
+    #[test]
+    fn invalid_timeouts() {
+        for secs in [0.0, -1.0, f64::NAN, f64::INFINITY, 1e300] {
+            let config = Config {
+                timeout: secs,
+                ..Config::default()
+            };
+            assert_eq!(timeout(&config, Timeout::Web), Err("timeout"), "{}", secs);
+            let config = Config {
+                dbus_timeout: Some(secs),
+                ..Config::default()
+            };
+            assert_eq!(
+                timeout(&config, Timeout::Dbus),
+                Err("dbus_timeout"),
+                "{}",
+                secs
+            );
+        }
+    }
+
+    #[test]
+    #[cfg(any(feature = "search", feature = "web-api"))]
+    fn empty_credentials_are_not_set() {
+        let credentials = |id: Option<&str>, secret: Option<&str>| Config {
+            client_id: id.map(str::to_string),
+            client_secret: secret.map(str::to_string),
+            ..Config::default()
+        };
+        assert_eq!(
+            credentials(Some("id"), Some("secret")).credentials(),
+            Some(("id", "secret"))
+        );
+        assert_eq!(credentials(Some("id"), None).credentials(), None);
+        assert_eq!(credentials(Some(""), Some("secret")).credentials(), None);
+        assert_eq!(credentials(Some("id"), Some(" ")).credentials(), None);
+        assert_eq!(credentials(Some(""), Some("")).credentials(), None);
+    }
+}
@@ -0,0 +1,416 @@
+use std::{io::IsTerminal, time::Duration};
+
+use clap::ValueEnum;
+use regex::Regex;
+
+use crate::{config::Config, Error};
+
+/// Patterns used by `--trim-feat` when none are configured
+pub const DEFAULT_TRIM_PATTERNS: &[&str] = &[
+    r"(?i)\s*[(\[](feat\.|ft\.|featuring|with) [^)\]]*[)\]]",
+    r"(?i)\s+-\s+(\d{4}\s+)?remaster(ed)?(\s+\d{4})?(\s+version)?$",
+];
+
+/// How names are shown to the user, raw data like URIs is never changed by this
+#[derive(Debug, Default)]
+pub struct DisplayOptions {
+    /// Strips clutter like "(feat. X)" from names when set
+    trim_patterns: Option<Vec<Regex>>,
+    /// Only this many artists are shown when set, followed by "+N more"
+    max_artists: Option<usize>,
+    /// Whether [`DisplayOptions::paint`] adds colors, see [`ColorChoice::enabled`]
+    pub color: bool,
+}
+
+impl DisplayOptions {
+    pub fn from_config(config: &Config) -> Result<DisplayOptions, Error> {
+        let trim_patterns = if config.trim_feat {
+            let patterns = config
+                .trim_patterns
+                .iter()
+                .map(|pattern| Regex::new(pattern))
+                .collect::<Result<_, _>>()?;
+            Some(patterns)
+        } else {
+            None
+        };
+        if config.max_artists == Some(0) {
+            return Err(Error::InvalidMaxArtists);
+        }
+        Ok(DisplayOptions {
+            trim_patterns,
+            max_artists: config.max_artists,
+            color: false,
+        })
+    }
+
+    /// Wraps `text` in the ANSI escape for `style`, or leaves it as it is without colors
+    pub fn paint(&self, text: &str, style: Style) -> String {
+        if self.color {
+            format!("\x1b[{}m{}\x1b[0m", style.code(), text)
+        } else {
+            text.to_string()
+        }
+    }
+
+    /// Joins the artists with commas, following the trim patterns and the artist cap
+    pub fn artists(&self, artists: &[String]) -> String {
+        let (shown, hidden) = self.cap_artists(artists);
+        let joined = shown
+            .iter()
+            .map(|artist| self.name(artist))
+            .collect::<Vec<_>>()
+            .join(", ");
+        if hidden > 0 {
+            format!("{} +{} more", joined, hidden)
+        } else {
+            joined
+        }
+    }
+
+    /// Splits `artists` in the ones that should be shown and how many are hidden
+    pub fn cap_artists<'a, T>(&self, artists: &'a [T]) -> (&'a [T], usize) {
+        match self.max_artists {
+            Some(max) if artists.len() > max => (&artists[..max], artists.len() - max),
+            _ => (artists, 0),
+        }
+    }
+
+    /// Removes everything the trim patterns match from `name`
+    pub fn name(&self, name: &str) -> String {
+        match &self.trim_patterns {
+            Some(patterns) => {
+                let mut name = name.to_string();
+                for pattern in patterns {
+                    name = pattern.replace_all(&name, "").into_owned();
+                }
+                name.trim().to_string()
+            }
+            None => name.to_string(),
+        }
+    }
+}
+
+/// Whether output is colored, with `--color`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum ColorChoice {
+    /// Only when stdout is a terminal and `NO_COLOR` isn't set
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    /// Decides once for all output, every colored path goes through [`DisplayOptions::paint`]
+    pub fn enabled(self) -> bool {
+        // https://no-color.org, set to anything but an empty string
+        let no_color = std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+        self.decide(no_color, std::io::stdout().is_terminal())
+    }
+
+    /// An explicit choice wins over `NO_COLOR`, which wins over the terminal check
+    fn decide(self, no_color: bool, terminal: bool) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => !no_color && terminal,
+        }
+    }
+}
+
+/// The few styles that are used, kept together so they look the same everywhere
+#[derive(Debug, Clone, Copy)]
+pub enum Style {
+    /// Labels and the numbers to pick from
+    Bold,
+    /// Less important details, like durations in lists
+    #[cfg(feature = "search")]
+    Dim,
+    Playing,
+    Paused,
+}
+
+impl Style {
+    fn code(self) -> &'static str {
+        match self {
+            Style::Bold => "1",
+            #[cfg(feature = "search")]
+            Style::Dim => "2",
+            Style::Playing => "32",
+            Style::Paused => "33",
+        }
+    }
+
+    /// The style a playback status like `Playing` is shown in
+    pub fn status(status: &str) -> Style {
+        match status {
+            "Playing" => Style::Playing,
+            _ => Style::Paused,
+        }
+    }
+}
+
+/// How durations are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum DurationStyle {
+    /// `3:45`, or `1:02:03` when it's an hour or longer
+    #[default]
+    Compact,
+    /// `03:45`, or `01:02:03` when it's an hour or longer, so the width only changes at an hour
+    Padded,
+}
+
+impl DurationStyle {
+    pub fn format(self, duration: Duration) -> String {
+        let secs = duration.as_secs();
+        let (hours, minutes, seconds) = (secs / 3600, secs / 60 % 60, secs % 60);
+        match (self, hours) {
+            (DurationStyle::Compact, 0) => format!("{}:{:02}", minutes, seconds),
+            (DurationStyle::Compact, _) => format!("{}:{:02}:{:02}", hours, minutes, seconds),
+            (DurationStyle::Padded, 0) => format!("{:02}:{:02}", minutes, seconds),
+            (DurationStyle::Padded, _) => format!("{:02}:{:02}:{:02}", hours, minutes, seconds),
+        }
+    }
+}
+
+/// Formats a duration as `m:ss`, or `h:mm:ss` when it's an hour or longer
+pub fn format_duration(duration: Duration) -> String {
+    DurationStyle::Compact.format(duration)
+}
+
+/// Draws a bar like `[=====>    ]` that is `width` characters wide, including the brackets
+pub fn progress_bar(position: Duration, length: Duration, width: usize) -> String {
+    let inner = width.saturating_sub(2);
+    let filled = if length.is_zero() {
+        0
+    } else {
+        let fraction = (position.as_secs_f64() / length.as_secs_f64()).clamp(0.0, 1.0);
+        (fraction * inner as f64).round() as usize
+    };
+    let bar = if filled == 0 {
+        " ".repeat(inner)
+    } else {
+        format!("{}>{}", "=".repeat(filled - 1), " ".repeat(inner - filled))
+    };
+    format!("[{}]", bar)
+}
+
+/// Spotify artwork urls start with this, followed by a code for the size and the id of the image
+const SPOTIFY_ARTWORK: &str = "https://i.scdn.co/image/ab67616d";
+/// The sizes Spotify has artwork in, with the code that is used in the url for them
+const SPOTIFY_ARTWORK_SIZES: &[(u32, &str)] =
+    &[(64, "00004851"), (300, "00001e02"), (640, "0000b273")];
+
+/// Changes a Spotify artwork url to the available size closest to `size`.
+/// Urls from other players are returned unchanged.
+pub fn sized_artwork(url: &str, size: u32) -> String {
+    let rest = match url.strip_prefix(SPOTIFY_ARTWORK) {
+        Some(rest) if rest.len() > 8 && rest.is_char_boundary(8) => rest,
+        _ => return url.to_string(),
+    };
+    let (_, code) = SPOTIFY_ARTWORK_SIZES
+        .iter()
+        .min_by_key(|(available, _)| available.abs_diff(size))
+        .unwrap();
+    format!("{}{}{}", SPOTIFY_ARTWORK, code, &rest[8..])
+}
+
+/// Replaces every `{token}` in `template` with what `lookup` returns for it.
+/// Tokens `lookup` doesn't know are left as they are.
+pub fn render(template: &str, lookup: impl Fn(&str) -> Option<String>) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let value = rest
+            .find('}')
+            .and_then(|end| lookup(&rest[1..end]).map(|value| (value, end)));
+        match value {
+            Some((value, end)) => {
+                out.push_str(&value);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                out.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn color_decision_matrix() {
+        // (choice, NO_COLOR set, stdout is a terminal) => colored
+        let matrix = [
+            (ColorChoice::Auto, false, false, false),
+            (ColorChoice::Auto, false, true, true),
+            (ColorChoice::Auto, true, false, false),
+            (ColorChoice::Auto, true, true, false),
+            (ColorChoice::Always, false, false, true),
+            (ColorChoice::Always, false, true, true),
+            (ColorChoice::Always, true, false, true),
+            (ColorChoice::Always, true, true, true),
+            (ColorChoice::Never, false, false, false),
+            (ColorChoice::Never, false, true, false),
+            (ColorChoice::Never, true, false, false),
+            (ColorChoice::Never, true, true, false),
+        ];
+        for (choice, no_color, terminal, colored) in matrix {
+            assert_eq!(
+                choice.decide(no_color, terminal),
+                colored,
+                "{:?} with NO_COLOR {} and a terminal {}",
+                choice,
+                no_color,
+                terminal
+            );
+        }
+    }
+
+    #[test]
+    fn painting() {
+        let mut options = DisplayOptions::default();
+        assert_eq!(options.paint("0", Style::Bold), "0");
+        options.color = true;
+        assert_eq!(options.paint("0", Style::Bold), "\x1b[1m0\x1b[0m");
+    }
+
+    #[test]
+    fn showing_no_artists_is_rejected() {
+        let config = Config {
+            max_artists: Some(0),
+            ..Config::default()
+        };
+        assert!(matches!(
+            DisplayOptions::from_config(&config),
+            Err(Error::InvalidMaxArtists)
+        ));
+    }
+
+    #[test]
+    fn capped_artists() {
+        let config = Config {
+            max_artists: Some(1),
+            ..Config::default()
+        };
+        let options = DisplayOptions::from_config(&config).unwrap();
+        let artists = ["A".to_string(), "B".to_string(), "C".to_string()];
+        assert_eq!(options.artists(&artists), "A +2 more");
+        assert_eq!(options.artists(&artists[..1]), "A");
+        assert_eq!(DisplayOptions::default().artists(&artists), "A, B, C");
+    }
+
+    #[test]
+    fn durations() {
+        let cases = [
+            (0, "0:00", "00:00"),
+            (59, "0:59", "00:59"),
+            (225, "3:45", "03:45"),
+            (3599, "59:59", "59:59"),
+            (3600, "1:00:00", "01:00:00"),
+            (3723, "1:02:03", "01:02:03"),
+        ];
+        for (secs, compact, padded) in cases {
+            let duration = Duration::from_secs(secs);
+            assert_eq!(DurationStyle::Compact.format(duration), compact);
+            assert_eq!(DurationStyle::Padded.format(duration), padded);
+        }
+        // Parts of a second are cut off rather than rounded up
+        assert_eq!(format_duration(Duration::from_millis(59_999)), "0:59");
+    }
+
+    #[test]
+    fn templates() {
+        let lookup = |token: &str| match token {
+            "title" => Some("Song".to_string()),
+            "artist" => Some("A".to_string()),
+            "empty" => Some(String::new()),
+            _ => None,
+        };
+        let cases = [
+            ("", ""),
+            ("no tokens", "no tokens"),
+            ("{title}", "Song"),
+            ("{title} - {artist}", "Song - A"),
+            ("{title}{artist}", "SongA"),
+            ("[{empty}]", "[]"),
+            // Unknown and unclosed tokens are left as they are
+            ("{unknown} {title}", "{unknown} Song"),
+            ("{title", "{title"),
+            ("title}", "title}"),
+            ("{}", "{}"),
+            ("{{title}}", "{Song}"),
+            ("{♥} {title}", "{♥} Song"),
+        ];
+        for (template, rendered) in cases {
+            assert_eq!(render(template, lookup), rendered, "{}", template);
+        }
+    }
+
+    #[test]
+    fn progress_bars() {
+        // (position, length, width) => bar, in seconds
+        let cases = [
+            (0, 100, 12, "[          ]"),
+            (10, 100, 12, "[>         ]"),
+            (50, 100, 12, "[====>     ]"),
+            (100, 100, 12, "[=========>]"),
+            // Past the end, like when the length is wrong, stays full
+            (150, 100, 12, "[=========>]"),
+            // Without a length there's nothing to show
+            (50, 0, 12, "[          ]"),
+            (1, 3, 5, "[>  ]"),
+            (50, 100, 2, "[]"),
+            (50, 100, 0, "[]"),
+        ];
+        for (position, length, width, bar) in cases {
+            let (position, length) = (Duration::from_secs(position), Duration::from_secs(length));
+            assert_eq!(progress_bar(position, length, width), bar);
+        }
+    }
+
+    #[test]
+    fn artwork_sizes() {
+        let url = |code: &str| format!("https://i.scdn.co/image/ab67616d{}0123456789abcdef", code);
+        let cases = [
+            (0, "00004851"),
+            (64, "00004851"),
+            (181, "00004851"),
+            (183, "00001e02"),
+            (300, "00001e02"),
+            (470, "00001e02"),
+            (471, "0000b273"),
+            (640, "0000b273"),
+            (4000, "0000b273"),
+        ];
+        for (size, code) in cases {
+            for from in ["00004851", "00001e02", "0000b273"] {
+                assert_eq!(
+                    sized_artwork(&url(from), size),
+                    url(code),
+                    "{} from {}",
+                    size,
+                    from
+                );
+            }
+        }
+        // Urls that aren't Spotify artwork, or are cut short, are left alone
+        for other in [
+            "",
+            "file:///tmp/cover.png",
+            "https://i.scdn.co/image/ab67616d",
+            "https://i.scdn.co/image/ab67616d00001e02",
+            "https://example.com/image/ab67616d00001e02abc",
+        ] {
+            assert_eq!(sized_artwork(other, 64), other);
+        }
+    }
+}
@@ -0,0 +1,165 @@
+use std::time::Duration;
+
+#[cfg(any(feature = "search", feature = "notifications"))]
+use reqwest::Client;
+#[cfg(feature = "notifications")]
+use reqwest::Url;
+use reqwest::{
+    header::{HeaderMap, RETRY_AFTER},
+    RequestBuilder, Response, StatusCode,
+};
+
+use crate::Error;
+
+/// The longest we're willing to wait in total when being rate limited
+const MAX_RATE_LIMIT_WAIT: Duration = Duration::from_secs(30);
+
+/// Sends a GET request, waiting and retrying when rate limited like [`send`]
+#[cfg(any(feature = "search", feature = "notifications"))]
+pub async fn get(client: &Client, url: &str) -> Result<Response, Error> {
+    send(client.get(url)).await
+}
+
+/// Sends a request, waiting and retrying as long as the server responds with
+/// 429 Too Many Requests, as long as that doesn't take longer than [`MAX_RATE_LIMIT_WAIT`].
+pub async fn send(request: RequestBuilder) -> Result<Response, Error> {
+    let mut waited = Duration::ZERO;
+    loop {
+        // Requests with a body that is streamed can't be retried, just send those once
+        let res = match request.try_clone() {
+            Some(request) => request.send().await?,
+            None => return Ok(request.send().await?.error_for_status()?),
+        };
+        if res.status() != StatusCode::TOO_MANY_REQUESTS {
+            return Ok(res.error_for_status()?);
+        }
+
+        let wait = retry_after(res.headers()).unwrap_or(Duration::from_secs(1));
+        waited += wait;
+        if waited > MAX_RATE_LIMIT_WAIT {
+            return Err(Error::RateLimited);
+        }
+        tokio::time::sleep(wait).await;
+    }
+}
+
+/// Reads the Retry-After header, only the number of seconds form is supported
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let secs = headers.get(RETRY_AFTER)?.to_str().ok()?.parse().ok()?;
+    Some(Duration::from_secs(secs))
+}
+
+/// Downloads the artwork at `url`, failing with [`Error::ArtworkTooLarge`] when it's more than
+/// `max_bytes`. Artwork in a `data:` URI is decoded without touching the network, since some
+/// players embed it like that. Other than that only `http` and `https` urls can be downloaded.
+#[cfg(feature = "notifications")]
+pub async fn artwork(client: &Client, url: &str, max_bytes: u64) -> Result<Vec<u8>, Error> {
+    let too_large = |len: u64| len > max_bytes;
+    if let Some(data) = url.strip_prefix("data:") {
+        let bytes = data_uri(data)?;
+        if too_large(bytes.len() as u64) {
+            return Err(Error::ArtworkTooLarge(max_bytes));
+        }
+        return Ok(bytes);
+    }
+
+    // reqwest's own error for these doesn't say which url it was about
+    match Url::parse(url).as_ref().map(Url::scheme) {
+        Ok("http" | "https") => {}
+        _ => return Err(Error::UnsupportedArtworkUrl(url.to_string())),
+    }
+    let mut res = get(client, url).await?;
+    if res.content_length().is_some_and(too_large) {
+        return Err(Error::ArtworkTooLarge(max_bytes));
+    }
+    // The length doesn't have to be sent, or could be wrong, so it's checked while downloading too
+    let mut bytes = Vec::new();
+    while let Some(chunk) = res.chunk().await? {
+        bytes.extend_from_slice(&chunk);
+        if too_large(bytes.len() as u64) {
+            return Err(Error::ArtworkTooLarge(max_bytes));
+        }
+    }
+    Ok(bytes)
+}
+
+/// Decodes what comes after `data:`, which is base64 when it says so and percent-encoded otherwise
+#[cfg(feature = "notifications")]
+fn data_uri(data: &str) -> Result<Vec<u8>, Error> {
+    let (kind, data) = data.split_once(',').ok_or(Error::InvalidDataUri)?;
+    if kind.ends_with(";base64") {
+        return base64::decode(data).map_err(|_| Error::InvalidDataUri);
+    }
+    let mut bytes = Vec::with_capacity(data.len());
+    let mut rest = data.as_bytes();
+    while let Some((&byte, after)) = rest.split_first() {
+        rest = after;
+        if byte != b'%' {
+            bytes.push(byte);
+            continue;
+        }
+        // Both digits have to be there, a lone % isn't valid
+        let hex = rest
+            .get(..2)
+            .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|hex| std::str::from_utf8(hex).ok());
+        let byte = hex.and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        bytes.push(byte.ok_or(Error::InvalidDataUri)?);
+        rest = &rest[2..];
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::header::HeaderValue;
+
+    use super::*;
+
+    #[test]
+    fn retry_after_header() {
+        let cases = [
+            ("0", Some(0)),
+            ("5", Some(5)),
+            ("120", Some(120)),
+            // The date form isn't supported, so the default wait is used for it
+            ("Wed, 21 Oct 2026 07:28:00 GMT", None),
+            ("-1", None),
+            ("1.5", None),
+            ("", None),
+        ];
+        for (value, secs) in cases {
+            let mut headers = HeaderMap::new();
+            headers.insert(RETRY_AFTER, HeaderValue::from_static(value));
+            assert_eq!(
+                retry_after(&headers),
+                secs.map(Duration::from_secs),
+                "{}",
+                value
+            );
+        }
+        assert_eq!(retry_after(&HeaderMap::new()), None);
+    }
+
+    #[test]
+    #[cfg(feature = "notifications")]
+    fn data_uris() {
+        let cases: [(&str, &[u8]); 5] = [
+            ("image/png;base64,iVBORw==", b"\x89PNG"),
+            (";base64,", b""),
+            ("image/svg+xml,<svg%20width='1'/>", b"<svg width='1'/>"),
+            (",100%25%0a", b"100%\n"),
+            (",%E2%99%A5", "\u{2665}".as_bytes()),
+        ];
+        for (data, bytes) in cases {
+            assert_eq!(data_uri(data).unwrap(), bytes, "{}", data);
+        }
+        for data in ["image/png", ";base64,!!", ",%", ",%4", ",%zz", ",%+1"] {
+            assert!(
+                matches!(data_uri(data), Err(Error::InvalidDataUri)),
+                "{}",
+                data
+            );
+        }
+    }
+}
@@ -1,14 +1,58 @@
-use std::{collections::HashMap, fmt::Display, io::Write, vec};
+use std::{
+    collections::HashMap, fmt::Display, future::Future, io::Write, path::PathBuf, time::Duration,
+    vec,
+};
 
-use clap::{Parser, Subcommand};
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
+use futures_util::{Stream, StreamExt};
 
-use notify_rust::{Hint, Notification};
-use serde::{Deserialize, Serialize};
+use config::{Config, Timeout};
+use display::{
+    format_duration, progress_bar, render, ColorChoice, DisplayOptions, DurationStyle, Style,
+};
+use notify::{Message, NotifyBackend};
+#[cfg(feature = "search")]
+use search::{ResolvedTrack, SearchBackend, SortOrder, Track};
+use serde::Serialize;
 use zbus::{
-    dbus_proxy,
-    zvariant::{OwnedValue, Value},
+    dbus_proxy, fdo,
+    zvariant::{ObjectPath, OwnedObjectPath, OwnedValue, Value},
+    Connection,
 };
 
+/// The service that is used when none is given on the command line or in the environment
+const DEFAULT_SERVICE: &str = "org.mpris.MediaPlayer2.spotify";
+/// The optional features this was built with, printed by `version --verbose`
+const FEATURES: &[&str] = &[
+    #[cfg(feature = "notifications")]
+    "notifications",
+    #[cfg(feature = "search")]
+    "search",
+    #[cfg(feature = "web-api")]
+    "web-api",
+    #[cfg(feature = "http-server")]
+    "http-server",
+    #[cfg(feature = "tui")]
+    "tui",
+];
+/// Every MPRIS compatible player registers a name starting with this
+const MPRIS_PREFIX: &str = "org.mpris.MediaPlayer2.";
+
+#[cfg(feature = "search")]
+mod cache;
+mod config;
+mod display;
+#[cfg(feature = "http-client")]
+mod http;
+mod notify;
+#[cfg(feature = "search")]
+mod search;
+#[cfg(feature = "http-server")]
+mod server;
+mod state;
+#[cfg(feature = "web-api")]
+mod web_api;
+
 #[dbus_proxy(
     interface = "org.mpris.MediaPlayer2.Player",
     default_path = "/org/mpris/MediaPlayer2",
@@ -16,31 +60,386 @@ use zbus::{
 )]
 trait Player {
     fn play_pause(&self) -> zbus::Result<()>;
+    fn play(&self) -> zbus::Result<()>;
+    fn pause(&self) -> zbus::Result<()>;
+    fn stop(&self) -> zbus::Result<()>;
     fn next(&self) -> zbus::Result<()>;
     fn previous(&self) -> zbus::Result<()>;
     fn open_uri(&self, uri: &str) -> zbus::Result<()>;
+    fn seek(&self, offset: i64) -> zbus::Result<()>;
+    fn set_position(&self, track_id: &ObjectPath<'_>, position: i64) -> zbus::Result<()>;
+    #[dbus_proxy(property)]
+    fn metadata(&self) -> zbus::Result<OwnedValue>;
+    #[dbus_proxy(property)]
+    fn playback_status(&self) -> zbus::Result<String>;
+    #[dbus_proxy(property(emits_changed_signal = "false"))]
+    fn position(&self) -> zbus::Result<i64>;
+    #[dbus_proxy(property)]
+    fn volume(&self) -> zbus::Result<OwnedValue>;
+    #[dbus_proxy(property)]
+    fn set_volume(&self, value: Value<'_>) -> zbus::Result<()>;
+    #[dbus_proxy(property)]
+    fn shuffle(&self) -> zbus::Result<bool>;
+    #[dbus_proxy(property)]
+    fn loop_status(&self) -> zbus::Result<String>;
+    #[dbus_proxy(property)]
+    fn set_rate(&self, value: f64) -> zbus::Result<()>;
+    #[dbus_proxy(property)]
+    fn minimum_rate(&self) -> zbus::Result<f64>;
+    #[dbus_proxy(property)]
+    fn maximum_rate(&self) -> zbus::Result<f64>;
+}
+
+/// The player application itself, served by every MPRIS player next to [`PlayerProxy`]
+#[dbus_proxy(
+    interface = "org.mpris.MediaPlayer2",
+    default_path = "/org/mpris/MediaPlayer2",
+    default_service = "org.mpris.MediaPlayer2.spotify"
+)]
+trait MediaPlayer2 {
+    fn raise(&self) -> zbus::Result<()>;
+    fn quit(&self) -> zbus::Result<()>;
+    #[dbus_proxy(property)]
+    fn can_raise(&self) -> zbus::Result<bool>;
     #[dbus_proxy(property)]
-    fn metadata(&self) -> zbus::Result<Metadata>;
+    fn can_quit(&self) -> zbus::Result<bool>;
+}
+
+/// Screen lockers report through this when the screen locks and unlocks
+#[dbus_proxy(
+    interface = "org.freedesktop.ScreenSaver",
+    default_path = "/org/freedesktop/ScreenSaver",
+    default_service = "org.freedesktop.ScreenSaver"
+)]
+trait ScreenSaver {
+    #[dbus_proxy(signal)]
+    fn active_changed(&self, active: bool) -> zbus::Result<()>;
 }
 
 #[derive(Debug)]
 pub enum Error {
     ZbusError(zbus::Error),
     MetadataError(MetadataError),
+    NoArtwork,
+    IoError(std::io::Error),
+    ConfigError(toml::de::Error),
+    ConfigExists(PathBuf),
+    NoConfigDir,
+    InvalidDefaultCommand(String),
+    /// The config key of a timeout that isn't a positive number of seconds
+    InvalidTimeout(&'static str),
+    /// `max_artists = 0` would hide every artist behind "+N more"
+    InvalidMaxArtists,
+    InvalidCount,
+    TimedOut(Timeout, Duration),
+    InvalidPattern(regex::Error),
+    RateOutOfRange {
+        rate: f64,
+        min: f64,
+        max: f64,
+    },
+    #[cfg(any(feature = "search", feature = "web-api"))]
+    Cancelled,
+    #[cfg(feature = "search")]
+    InvalidSelection(String),
+    #[cfg(feature = "search")]
+    NoSuchResult {
+        index: usize,
+        found: usize,
+    },
+    #[cfg(feature = "http-client")]
+    HttpError(reqwest::Error),
+    #[cfg(feature = "http-server")]
+    HttpServerError(hyper::Error),
+    #[cfg(feature = "http-client")]
+    RateLimited,
+    #[cfg(any(feature = "search", feature = "web-api"))]
+    MissingCredentials,
+    /// The spotify search backend was picked in a build without the web-api feature
+    #[cfg(all(feature = "search", not(feature = "web-api")))]
+    WithoutWebApi,
+    #[cfg(feature = "web-api")]
+    MissingRefreshToken,
+    #[cfg(feature = "web-api")]
+    NoDefaultDevice,
+    /// There's no online device with this name
+    #[cfg(feature = "web-api")]
+    DeviceOffline(String),
+    InvalidVolume,
+    #[cfg(feature = "search")]
+    NoCacheDir,
+    /// A search didn't find any songs
+    #[cfg(feature = "search")]
+    NotFound(String),
+    /// The same, when it was already printed as JSON so there's nothing left to say
+    #[cfg(feature = "search")]
+    NotFoundPrinted,
+    /// No command was given and there's no `default_command` either
+    NoCommand,
+    /// `play-song last` was used without a recent `--list` search
+    #[cfg(feature = "search")]
+    NoLastSearch,
+    /// SetPosition needs the track id of the current song
+    NoTrackId,
+    /// The Web API only knows about Spotify tracks
+    #[cfg(feature = "web-api")]
+    NoSpotifyTrack,
+    SeekPastEnd {
+        to: Duration,
+        length: Duration,
+    },
+    /// A time that doesn't fit in the microseconds MPRIS uses for positions
+    SeekTooFar(Duration),
+    /// A spotify: uri was about to be opened by another player
+    NotSpotify(String),
+    /// A `--service-name` with a `*` that no name on the bus matches
+    NoMatchingService(String),
+    /// Skipping several songs stopped halfway
+    PartialSkip {
+        done: u32,
+        count: u32,
+        error: Box<Error>,
+    },
+    #[cfg(feature = "notifications")]
+    InvalidDataUri,
+    #[cfg(feature = "notifications")]
+    ArtworkTooLarge(u64),
+    /// Artwork that isn't at an http, https or data: url
+    #[cfg(feature = "notifications")]
+    UnsupportedArtworkUrl(String),
+    #[cfg(feature = "notifications")]
+    NotificationError(notify_rust::error::Error),
+    NotifySendFailed(std::process::ExitStatus),
+    /// `--notify-backend notify-send` was used without it being installed
+    NoNotifySend,
+}
+
+#[cfg(feature = "notifications")]
+impl From<notify_rust::error::Error> for Error {
+    fn from(e: notify_rust::error::Error) -> Self {
+        Error::NotificationError(e)
+    }
+}
+
+#[cfg(feature = "http-client")]
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        Error::HttpError(e)
+    }
+}
+
+impl From<regex::Error> for Error {
+    fn from(e: regex::Error) -> Self {
+        Error::InvalidPattern(e)
+    }
+}
+
+#[cfg(feature = "http-server")]
+impl From<hyper::Error> for Error {
+    fn from(e: hyper::Error) -> Self {
+        Error::HttpServerError(e)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::IoError(e)
+    }
+}
+
+impl From<toml::de::Error> for Error {
+    fn from(e: toml::de::Error) -> Self {
+        Error::ConfigError(e)
+    }
+}
+
+impl From<zbus::Error> for Error {
+    fn from(e: zbus::Error) -> Self {
+        Error::ZbusError(e)
+    }
+}
+
+impl From<fdo::Error> for Error {
+    fn from(e: fdo::Error) -> Self {
+        Error::ZbusError(e.into())
+    }
+}
+
+/// Exit codes of the failures that scripts may want to tell apart from the rest.
+/// Invalid arguments exit with 2 like they do from clap.
+const EXIT_ERROR: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+#[cfg(feature = "search")]
+const EXIT_NOT_FOUND: i32 = 3;
+
+impl Error {
+    fn exit_code(&self) -> i32 {
+        match self {
+            #[cfg(feature = "search")]
+            Error::NotFound(_) | Error::NotFoundPrinted => EXIT_NOT_FOUND,
+            Error::NoCommand => EXIT_USAGE,
+            _ => EXIT_ERROR,
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::ZbusError(e) => write!(f, "DBus error: {}", e),
+            Error::MetadataError(MetadataError::InvalidValueType(key)) => {
+                write!(f, "Metadata has an invalid value for {}", key)
+            }
+            Error::NoArtwork => write!(f, "The current song has no artwork"),
+            Error::IoError(e) => write!(f, "{}", e),
+            Error::ConfigError(e) => write!(f, "Invalid config file: {}", e),
+            Error::ConfigExists(path) => write!(
+                f,
+                "{} already exists, use --force to overwrite it",
+                path.display()
+            ),
+            Error::NoConfigDir => write!(f, "Couldn't find the config directory"),
+            Error::InvalidTimeout(key) => write!(
+                f,
+                "Invalid {} in the config file, it has to be a positive number of seconds",
+                key
+            ),
+            Error::TimedOut(kind, after) => {
+                let what = match kind {
+                    Timeout::Dbus => "the player",
+                    Timeout::Search => "the search",
+                    Timeout::Artwork => "the artwork",
+                    Timeout::Web => "the Spotify Web API",
+                };
+                write!(f, "Gave up on {} after {:.1}s", what, after.as_secs_f64())
+            }
+            Error::InvalidCount => {
+                write!(f, "Invalid count in the config file, it has to be at least 1")
+            }
+            Error::InvalidMaxArtists => write!(
+                f,
+                "Invalid max_artists in the config file, it has to be at least 1"
+            ),
+            Error::InvalidDefaultCommand(e) => {
+                write!(f, "Invalid default_command in the config file: {}", e.trim())
+            }
+            Error::InvalidPattern(e) => write!(f, "Invalid trim pattern: {}", e),
+            Error::RateOutOfRange { rate, min, max } => write!(
+                f,
+                "A rate of {} isn't supported, it has to be between {} and {} (use --clamp to use the closest supported rate)",
+                rate, min, max
+            ),
+            #[cfg(any(feature = "search", feature = "web-api"))]
+            Error::Cancelled => write!(f, "Cancelled, no song was picked"),
+            #[cfg(feature = "search")]
+            Error::InvalidSelection(input) => write!(f, "{} isn't one of the listed songs", input),
+            #[cfg(feature = "search")]
+            Error::NoSuchResult { index, found } => write!(
+                f,
+                "There is no result {}, the search only found {} songs",
+                index, found
+            ),
+            #[cfg(feature = "http-client")]
+            Error::HttpError(e) => write!(f, "Request failed: {}", e),
+            #[cfg(feature = "http-server")]
+            Error::HttpServerError(e) => write!(f, "HTTP server error: {}", e),
+            #[cfg(feature = "http-client")]
+            Error::RateLimited => write!(f, "Rate limited for too long, try again later"),
+            #[cfg(feature = "web-api")]
+            Error::MissingRefreshToken => write!(
+                f,
+                "This needs refresh_token in the config file, run spotify-control auth to get one"
+            ),
+            #[cfg(feature = "web-api")]
+            Error::NoDefaultDevice => write!(f, "There's no default_device in the config file"),
+            #[cfg(feature = "web-api")]
+            Error::DeviceOffline(name) => write!(f, "The device {} isn't online", name),
+            #[cfg(any(feature = "search", feature = "web-api"))]
+            Error::MissingCredentials => write!(
+                f,
+                "The Spotify Web API needs client_id and client_secret in the config file"
+            ),
+            #[cfg(all(feature = "search", not(feature = "web-api")))]
+            Error::WithoutWebApi => write!(
+                f,
+                "This was built without the web-api feature, so it can only search with heroku"
+            ),
+            Error::InvalidVolume => write!(f, "The player reported a volume that isn't a number"),
+            #[cfg(feature = "search")]
+            Error::NotFound(query) => write!(f, "No track found for {}", query),
+            #[cfg(feature = "search")]
+            Error::NotFoundPrinted => write!(f, "No track found"),
+            Error::NoCommand => write!(f, "No command was given"),
+            #[cfg(feature = "search")]
+            Error::NoCacheDir => write!(f, "Couldn't find the cache directory"),
+            #[cfg(feature = "search")]
+            Error::NoLastSearch => write!(
+                f,
+                "There is no recent search to pick from, use play-song search --list first"
+            ),
+            Error::NoTrackId => write!(
+                f,
+                "The current song has no valid track id, which is needed to jump to a time"
+            ),
+            #[cfg(feature = "web-api")]
+            Error::NoSpotifyTrack => write!(f, "The current song isn't a Spotify track"),
+            Error::SeekPastEnd { to, length } => write!(
+                f,
+                "Can't jump to {}, the song is only {} long",
+                format_duration(*to),
+                format_duration(*length)
+            ),
+            Error::SeekTooFar(to) => write!(f, "Can't jump to {}, that's too far", format_duration(*to)),
+            Error::NoMatchingService(pattern) => {
+                write!(f, "There's no service on the bus that matches {}", pattern)
+            }
+            Error::NotSpotify(service) => write!(
+                f,
+                "{} isn't Spotify, it probably can't play spotify: uris like the ones search finds (use --force to try anyway)",
+                service
+            ),
+            Error::PartialSkip { done, count, error } => write!(
+                f,
+                "Only skipped {} of {} songs, then: {}",
+                done, count, error
+            ),
+            #[cfg(feature = "notifications")]
+            Error::ArtworkTooLarge(max) => write!(f, "The artwork is larger than {} bytes", max),
+            #[cfg(feature = "notifications")]
+            Error::InvalidDataUri => write!(f, "The artwork is an invalid data: URI"),
+            #[cfg(feature = "notifications")]
+            Error::UnsupportedArtworkUrl(url) => write!(
+                f,
+                "Can't get the artwork at {}, only http, https and data: urls are supported",
+                url
+            ),
+            #[cfg(feature = "notifications")]
+            Error::NotificationError(e) => write!(f, "{}", e),
+            Error::NotifySendFailed(status) => write!(f, "notify-send failed ({})", status),
+            Error::NoNotifySend => write!(f, "notify-send isn't installed"),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub enum MetadataError {
-    MissingKey(String),
     InvalidValueType(String),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct Metadata {
     r#title: String,
     artists: Vec<String>,
+    /// Empty if the song isn't part of an album, which happens for some singles
     album: String,
+    /// Empty if the player doesn't provide any artwork
     artwork: String,
+    /// The DBus object path identifying the track, empty if the player doesn't provide one
+    trackid: String,
+    /// Empty if the player doesn't provide one
+    url: String,
+    /// In microseconds, like MPRIS itself
+    length: Option<i64>,
 }
 
 impl TryInto<OwnedValue> for Metadata {
@@ -51,216 +450,3439 @@ impl TryInto<OwnedValue> for Metadata {
         map.insert("xesam:artist".to_string(), Value::new(self.artists));
         map.insert("xesam:album".to_string(), Value::new(self.album));
         map.insert("mpris:artUrl".to_string(), Value::new(self.artwork));
+        map.insert("xesam:url".to_string(), Value::new(self.url));
+        if let Some(length) = self.length {
+            map.insert("mpris:length".to_string(), Value::new(length));
+        }
+        if !self.trackid.is_empty() {
+            let trackid = ObjectPath::try_from(self.trackid)?;
+            map.insert("mpris:trackid".to_string(), Value::new(trackid));
+        }
         Ok(Value::Dict(map.into()).into())
     }
 }
 
-impl Into<Metadata> for OwnedValue {
-    fn into(self) -> Metadata {
-        let mut map: HashMap<String, Value<'_>> = HashMap::new();
-        if let Value::Dict(dict) = self.into() {
-            map = dict.try_into().unwrap();
-        }
-        let title = map.get("xesam:title").cloned().unwrap().downcast().unwrap();
-        let artists = map
-            .get("xesam:artist")
-            .cloned()
-            .unwrap()
-            .downcast()
-            .unwrap();
-        let album = map.get("xesam:album").cloned().unwrap().downcast().unwrap();
-        let artwork = map
-            .get("mpris:artUrl")
-            .cloned()
-            .unwrap()
-            .downcast()
-            .unwrap();
-
-        Metadata {
+/// The title of songs without a title or url
+const UNKNOWN_TITLE: &str = "Unknown";
+
+impl Metadata {
+    /// The Spotify id of the track, from the end of the trackid or the url.
+    /// `None` for other players, and for things like ads and podcasts.
+    #[cfg(feature = "web-api")]
+    fn spotify_id(&self) -> Option<&str> {
+        [&self.trackid, &self.url].into_iter().find_map(|s| {
+            let (kind, id) = s.rsplit_once('/')?;
+            (kind.ends_with("/track") && !id.is_empty()).then_some(id)
+        })
+    }
+
+    /// Whether this is the song `uri` points to. Spotify reports a track uri's id at the end of
+    /// both the trackid and the url, other players are expected to report the uri as the url.
+    fn is(&self, uri: &str) -> bool {
+        match uri.strip_prefix("spotify:track:") {
+            Some(id) => [&self.trackid, &self.url]
+                .iter()
+                .any(|s| s.rsplit('/').next() == Some(id)),
+            None => self.url == uri,
+        }
+    }
+}
+
+impl TryFrom<OwnedValue> for Metadata {
+    type Error = MetadataError;
+    fn try_from(value: OwnedValue) -> Result<Metadata, MetadataError> {
+        let map: HashMap<String, Value<'_>> = match value.into() {
+            Value::Dict(dict) => dict
+                .try_into()
+                .map_err(|_| MetadataError::InvalidValueType("metadata".to_string()))?,
+            _ => return Err(MetadataError::InvalidValueType("metadata".to_string())),
+        };
+
+        // Ads and streams often don't say who they're by
+        let artists = optional(&map, "xesam:artist")?.unwrap_or_default();
+        // Singles are sometimes sent without an album
+        let album = optional(&map, "xesam:album")?.unwrap_or_default();
+        let artwork = optional(&map, "mpris:artUrl")?.unwrap_or_default();
+        // The spec says this is an object path, but some players send a plain string
+        let trackid = match optional::<OwnedObjectPath>(&map, "mpris:trackid") {
+            Ok(path) => path.map(|path| path.to_string()),
+            Err(_) => optional(&map, "mpris:trackid")?,
+        }
+        .unwrap_or_default();
+        let url: String = optional(&map, "xesam:url")?.unwrap_or_default();
+        // Some streams and ads don't even have a title, the url at least says where it's from
+        let title = match optional(&map, "xesam:title")? {
+            Some(title) => title,
+            None if !url.is_empty() => url.clone(),
+            None => UNKNOWN_TITLE.to_string(),
+        };
+        // This should be an i64, but some players use an unsigned integer instead
+        let length = match optional::<i64>(&map, "mpris:length") {
+            Ok(length) => length,
+            Err(_) => optional::<u64>(&map, "mpris:length")?.map(|l| l as i64),
+        };
+
+        Ok(Metadata {
             title,
             artists,
             album,
             artwork,
-        }
+            trackid,
+            url,
+            length,
+        })
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Subcommand)]
+/// Gets `key` from the metadata if it exists, failing only if it has the wrong type
+fn optional<'a, T>(map: &HashMap<String, Value<'a>>, key: &str) -> Result<Option<T>, MetadataError>
+where
+    T: TryFrom<Value<'a>>,
+{
+    map.get(key)
+        .cloned()
+        .map(|v| {
+            v.downcast()
+                .ok_or_else(|| MetadataError::InvalidValueType(key.to_string()))
+        })
+        .transpose()
+}
+
+impl From<MetadataError> for Error {
+    fn from(e: MetadataError) -> Self {
+        Error::MetadataError(e)
+    }
+}
+
+/// Reads and parses the metadata of the current song
+async fn metadata(proxy: &PlayerProxy<'_>) -> Result<Metadata, Error> {
+    Ok(proxy.metadata().await?.try_into()?)
+}
+
+/// Flags shared by the commands that control playback
+#[derive(Debug, Clone, PartialEq, Eq, clap::Args)]
+struct ControlArgs {
+    /// Show a notification with the new state and song afterwards
+    #[clap(short, long, action, global = true)]
+    notify: bool,
+
+    /// Print the new song and status as JSON afterwards, like a line of `watch --json`. When a
+    /// search finds nothing {"error":"not_found","query":"..."} is printed instead.
+    #[clap(long, action, global = true)]
+    json: bool,
+}
+
+/// Flags of `play-song` that work for both uris and searches
+#[derive(Debug, Clone, PartialEq, Eq, clap::Args)]
+struct PlayArgs {
+    /// Try to open spotify: uris even when the player isn't Spotify
+    #[clap(long, action)]
+    force: bool,
+
+    /// Write the uri of the song to this file, also with --resolve-only
+    #[clap(long, value_name = "PATH")]
+    write_uri: Option<PathBuf>,
+
+    /// Add the uri to the end of the --write-uri file instead of overwriting it
+    #[clap(long, action, requires = "write-uri")]
+    append: bool,
+
+    /// Warn when the player doesn't switch to the song, Spotify sometimes ignores uris
+    #[clap(long, action)]
+    verify: bool,
+
+    #[clap(flatten)]
+    control: ControlArgs,
+}
+
+#[derive(Debug, Clone, PartialEq, Subcommand)]
 enum Commands {
     /// Play the next song
-    Next,
+    Next {
+        /// Skip this many songs at once
+        #[clap(short, long, default_value = "1", value_parser = parse_skips)]
+        count: u32,
+
+        #[clap(flatten)]
+        control: ControlArgs,
+    },
     /// Play the previous song
-    Previous,
+    Previous {
+        /// Skip this many songs at once
+        #[clap(short, long, default_value = "1", value_parser = parse_skips)]
+        count: u32,
+
+        #[clap(flatten)]
+        control: ControlArgs,
+    },
+    /// Start playing
+    Play {
+        /// Start playing on this Spotify Connect device instead, through the Web API. `default`
+        /// is the `default_device` from the config file.
+        #[cfg(feature = "web-api")]
+        #[clap(long, value_name = "NAME", conflicts_with_all = &["notify", "json"])]
+        device: Option<String>,
+
+        #[clap(flatten)]
+        control: ControlArgs,
+    },
+    /// Pause playback
+    Pause {
+        #[clap(flatten)]
+        control: ControlArgs,
+    },
     /// Play/Pause the current song
-    PlayPause,
+    #[clap(visible_alias = "toggle")]
+    PlayPause {
+        #[clap(flatten)]
+        control: ControlArgs,
+    },
+    /// Stop playback
+    Stop {
+        #[clap(flatten)]
+        control: ControlArgs,
+    },
+    /// Save the current song in your library, this needs `refresh_token` in the config file
+    #[cfg(feature = "web-api")]
+    Like {
+        /// Remove it from your library instead
+        #[clap(long, action, conflicts_with = "toggle")]
+        unlike: bool,
+
+        /// Remove it when it's saved already, and save it otherwise
+        #[clap(long, action)]
+        toggle: bool,
+    },
+    /// Print whether the current song is saved in your library, true or false.
+    /// This needs `refresh_token` in the config file.
+    #[cfg(feature = "web-api")]
+    Saved {
+        /// Print a filled or an empty heart instead, for status bars
+        #[clap(long, action)]
+        icon: bool,
+    },
+    /// Bring the player's window to the front
+    Raise {
+        /// Try even when the player says it can't
+        #[clap(long, action)]
+        force: bool,
+    },
+    /// Close the player
+    Quit {
+        /// Try even when the player says it can't
+        #[clap(long, action)]
+        force: bool,
+    },
     /// Show a notification with the current song
-    NowPlaying,
+    #[clap(visible_alias = "np")]
+    NowPlaying {
+        /// The preferred size of the artwork in pixels, see `artwork-url --size`
+        #[clap(long, value_name = "PIXELS")]
+        artwork_size: Option<u32>,
+
+        /// Print a line in this format instead of showing a notification, for status bars.
+        /// Available are {title}, {artist}, {album}, {artwork}, {url}, {trackid}, {status},
+        /// {position}, {length} and {percent}, anything else is printed as is.
+        #[clap(short, long, value_name = "TEMPLATE")]
+        format: Option<String>,
+
+        /// How {position} and {length} are written, padded keeps the width the same for songs
+        /// shorter than an hour
+        #[clap(long, arg_enum, value_name = "STYLE", default_value = "compact")]
+        duration_style: DurationStyle,
+    },
+    /// Print the playback status, position, volume and the current song
+    Info {
+        /// Keep running and update the info in place
+        #[cfg(feature = "tui")]
+        #[clap(short, long, action)]
+        watch: bool,
+
+        /// How often the info is refreshed with --watch, in seconds
+        #[cfg(feature = "tui")]
+        #[clap(long, default_value = "1", value_parser = parse_seconds)]
+        interval: Duration,
+    },
+    /// Print the artwork url of the current song
+    ArtworkUrl {
+        /// The preferred size of the artwork in pixels. Spotify has artwork of 64, 300 and 640
+        /// pixels, the closest one is used. Artwork of other players is always used as is.
+        #[clap(long, value_name = "PIXELS")]
+        size: Option<u32>,
+    },
+    /// Print the metadata of the current song
+    Metadata {
+        /// Print JSON instead, with the fields title, artists, album, artwork, trackid, url and
+        /// length (in microseconds). Fields the player doesn't provide are empty or null.
+        #[clap(long, action)]
+        json: bool,
+
+        /// Keep running and print a line of JSON every time the song changes, without the
+        /// playback status changes `watch --json` also prints
+        #[clap(short, long, action)]
+        follow: bool,
+    },
     /// Play a song
     PlaySong {
         #[clap(subcommand)]
         mode: PlayMode,
+
+        #[clap(flatten)]
+        args: PlayArgs,
+    },
+    /// Keep running and show a notification every time the song changes
+    Watch {
+        /// Don't show notifications, useful together with --exec
+        #[clap(long, action)]
+        no_notify: bool,
+
+        /// Run a command through `sh -c` every time the song changes.
+        /// The song is passed in the environment as $SP_TITLE, $SP_ARTIST (joined with ", "),
+        /// $SP_ALBUM and $SP_ARTWORK.
+        #[clap(short, long, value_name = "CMD")]
+        exec: Option<String>,
+
+        /// Print a line of JSON whenever the song or the playback status changes,
+        /// instead of showing notifications
+        #[clap(long, action)]
+        json: bool,
+    },
+    /// Jump to a time in the current song, or forward or back from the current position
+    Seek {
+        /// The time to jump to, in seconds, m:ss or h:mm:ss
+        #[clap(
+            long,
+            value_name = "TIME",
+            value_parser = parse_time,
+            required_unless_present = "by",
+            conflicts_with = "by"
+        )]
+        to: Option<Duration>,
+
+        /// How far to jump, like +10 or -1:00, in the same format as --to
+        #[clap(
+            long,
+            value_name = "OFFSET",
+            allow_hyphen_values = true,
+            value_parser = parse_offset
+        )]
+        by: Option<i64>,
+    },
+    /// Change a setting of the player
+    Set {
+        #[clap(subcommand)]
+        property: SetProperty,
+    },
+    /// Serve a small JSON API to build remotes with: GET /status and /nowplaying,
+    /// POST /next, /prev and /playpause
+    #[cfg(feature = "http-server")]
+    Http {
+        #[clap(short, long, default_value = "8080")]
+        port: u16,
+
+        /// The address to listen on, use 0.0.0.0 to allow other devices on the network
+        #[clap(long, default_value = "127.0.0.1")]
+        bind: std::net::IpAddr,
+    },
+    /// Let the Web API act for you, which is needed for `play --device`. This prints a link to
+    /// open, and asks for the address it takes you to.
+    #[cfg(feature = "web-api")]
+    Auth,
+    /// Print the version, with --verbose also how it was built, for bug reports
+    Version {
+        /// Also print the git commit, the enabled features and the versions of the libraries
+        /// that talk to DBus and the web
+        #[clap(short, long, action)]
+        verbose: bool,
+    },
+    /// Print every name on the session bus, MPRIS players or not, to find out why a player
+    /// isn't found
+    #[clap(hide = true)]
+    DumpBusNames,
+    /// Print the configuration that is in effect, after applying the environment and flags
+    Config {
+        #[clap(subcommand)]
+        action: Option<ConfigAction>,
+
+        /// Print JSON instead of TOML
+        #[clap(long, action)]
+        json: bool,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Subcommand)]
+enum SetProperty {
+    /// Change the playback rate, 1.0 is the normal speed
+    Rate {
+        #[clap(value_parser = parse_rate)]
+        rate: f64,
+
+        /// Use the closest supported rate instead of failing when it's out of range
+        #[clap(long, action)]
+        clamp: bool,
+    },
+    /// Change the volume, 1.0 is the maximum
+    Volume {
+        #[clap(value_parser = parse_volume, required_unless_present = "relative")]
+        volume: Option<f64>,
+
+        /// Change the volume by this much instead, like +0.1 or -0.1. The new volume is kept
+        /// between 0.0 and 1.0 and printed.
+        #[clap(
+            long,
+            value_name = "DELTA",
+            allow_hyphen_values = true,
+            conflicts_with = "volume",
+            value_parser = parse_delta
+        )]
+        relative: Option<f64>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Subcommand)]
+enum ConfigAction {
+    /// Write a config file with all the defaults
+    Init {
+        /// Overwrite the config file if it already exists
+        #[clap(short, long, action)]
+        force: bool,
     },
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Subcommand)]
 enum PlayMode {
+    /// Play a uri, like one from the share menu of Spotify
     Uri {
         /// A uri in the format of spotify:track:<id>
         uri: String,
     },
+    /// Search for a song and play the first result, or one picked from a list
+    #[cfg(feature = "search")]
     Search {
-        /// You get the best success with "search title artist"
+        /// You get the best success with "search title artist". With more than one argument,
+        /// quoted ones are searched as a phrase, like `search "get lucky" daft punk`.
         query: Vec<String>,
 
         /// Allows picking from a list of songs instead of starting the first
         #[clap(short, long, action)]
         list: bool,
 
-        #[clap(short, long, default_value = "5")]
-        count: usize,
+        /// Play the result with this index instead of the first, counting from 0 like --list
+        #[clap(long, value_name = "N", conflicts_with = "list")]
+        select: Option<usize>,
+
+        /// Print the picked song as JSON, including its uri, instead of playing it
+        #[clap(long, action)]
+        resolve_only: bool,
+
+        #[clap(flatten)]
+        page: Page,
+
+        /// The order songs are listed and picked in, popularity only works with the spotify
+        /// search backend
+        #[clap(long, arg_enum, value_name = "ORDER", default_value = "relevance")]
+        sort: SortOrder,
+    },
+    /// Pick from the songs the last `search --list` showed, without searching again.
+    /// They're remembered for 10 minutes or until the next search.
+    #[cfg(feature = "search")]
+    Last {
+        /// Play the song with this index instead of asking
+        #[clap(long, value_name = "N")]
+        select: Option<usize>,
+
+        #[clap(flatten)]
+        page: Page,
+
+        /// Print the picked song as JSON, including its uri, instead of playing it
+        #[clap(long, action)]
+        resolve_only: bool,
     },
 }
 
+// Which part of a list of songs is used, the same for every command that lists them. Songs are
+// numbered from the start of the page, so `--select` counts from `--offset` as well. This isn't
+// a doc comment, clap would show it as the help of the commands it's flattened into.
+#[cfg(feature = "search")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::Args)]
+struct Page {
+    /// How many songs to list, defaults to `count` from the config file or 5.
+    /// Searches never return more than 50.
+    #[clap(short, long, value_parser = parse_count)]
+    count: Option<usize>,
+
+    /// Skip this many songs first
+    #[clap(long, value_name = "N", default_value = "0", value_parser = parse_page_offset)]
+    offset: usize,
+}
+
+#[cfg(feature = "search")]
+impl Page {
+    /// Leaves out the songs before the offset, failing when there aren't any left
+    fn skip(&self, mut tracks: Vec<Track>) -> Result<Vec<Track>, Error> {
+        if self.offset >= tracks.len() {
+            return Err(Error::NoSuchResult {
+                index: self.offset,
+                found: tracks.len(),
+            });
+        }
+        Ok(tracks.split_off(self.offset))
+    }
+
+    /// The songs to list, `default` of them when there's no `--count`
+    fn shown<'a>(&self, tracks: &'a [Track], default: usize) -> &'a [Track] {
+        // There can be fewer songs than were asked for
+        &tracks[..self.count.unwrap_or(default).min(tracks.len())]
+    }
+}
+
+/// Parses a volume between 0.0 and 1.0
+fn parse_volume(arg: &str) -> Result<f64, String> {
+    let volume = arg.parse::<f64>().map_err(|e| e.to_string())?;
+    if !(0.0..=1.0).contains(&volume) {
+        return Err("has to be between 0.0 and 1.0".to_string());
+    }
+    Ok(volume)
+}
+
+/// Parses a playback rate, MPRIS doesn't allow 0 or less
+fn parse_rate(arg: &str) -> Result<f64, String> {
+    let rate = arg.parse::<f64>().map_err(|e| e.to_string())?;
+    if !rate.is_finite() || rate <= 0.0 {
+        return Err("has to be a number more than 0".to_string());
+    }
+    Ok(rate)
+}
+
+/// Parses a change in volume, which may start with a + or -
+fn parse_delta(arg: &str) -> Result<f64, String> {
+    let delta = arg.parse::<f64>().map_err(|e| e.to_string())?;
+    if !(-1.0..=1.0).contains(&delta) {
+        return Err("has to be between -1.0 and 1.0".to_string());
+    }
+    Ok(delta)
+}
+
+/// Parses how many search results to list, searches never return more than 50
+#[cfg(feature = "search")]
+fn parse_count(arg: &str) -> Result<usize, String> {
+    let count = arg.parse::<usize>().map_err(|e| e.to_string())?;
+    if !(1..=50).contains(&count) {
+        return Err("has to be between 1 and 50".to_string());
+    }
+    Ok(count)
+}
+
+/// Parses how many songs to skip in a list, searches never return more than 50
+#[cfg(feature = "search")]
+fn parse_page_offset(arg: &str) -> Result<usize, String> {
+    let offset = arg.parse::<usize>().map_err(|e| e.to_string())?;
+    if offset >= 50 {
+        return Err("has to be less than 50".to_string());
+    }
+    Ok(offset)
+}
+
+/// Parses how many songs to skip, limited so a typo doesn't skip through a whole playlist
+fn parse_skips(arg: &str) -> Result<u32, String> {
+    let count = arg.parse::<u32>().map_err(|e| e.to_string())?;
+    if !(1..=MAX_SKIPS).contains(&count) {
+        return Err(format!("has to be between 1 and {}", MAX_SKIPS));
+    }
+    Ok(count)
+}
+
+/// Parses how many artists to show, at least one so a song never goes without them
+fn parse_max_artists(arg: &str) -> Result<usize, String> {
+    let max = arg.parse::<usize>().map_err(|e| e.to_string())?;
+    if max == 0 {
+        return Err("has to be at least 1".to_string());
+    }
+    Ok(max)
+}
+
+/// Parses a time in seconds (which may have a fraction), m:ss or h:mm:ss
+fn parse_time(arg: &str) -> Result<Duration, String> {
+    let invalid = || format!("{} isn't a time, use seconds, m:ss or h:mm:ss", arg);
+    let parts: Vec<&str> = arg.split(':').collect();
+    let (seconds, rest) = parts
+        .split_last()
+        .filter(|_| parts.len() <= 3)
+        .ok_or_else(invalid)?;
+    let seconds = seconds
+        .parse::<f64>()
+        .ok()
+        .and_then(|s| Duration::try_from_secs_f64(s).ok())
+        .ok_or_else(invalid)?;
+    // Only the first part may be 60 or more, 1:75 is more likely a typo than 2:15
+    if !rest.is_empty() && seconds.as_secs() >= 60 {
+        return Err(invalid());
+    }
+    let mut minutes: u64 = 0;
+    for (i, part) in rest.iter().enumerate() {
+        let value = part.parse::<u64>().map_err(|_| invalid())?;
+        if i > 0 && value >= 60 {
+            return Err(invalid());
+        }
+        minutes = minutes
+            .checked_mul(60)
+            .and_then(|minutes| minutes.checked_add(value))
+            .ok_or_else(invalid)?;
+    }
+    minutes
+        .checked_mul(60)
+        .and_then(|secs| Duration::from_secs(secs).checked_add(seconds))
+        .ok_or_else(invalid)
+}
+
+/// Parses a time like [`parse_time`] with an optional + or - in front, into microseconds
+fn parse_offset(arg: &str) -> Result<i64, String> {
+    let (sign, time) = match arg.strip_prefix('-') {
+        Some(time) => (-1, time),
+        None => (1, arg.strip_prefix('+').unwrap_or(arg)),
+    };
+    // MPRIS offsets are an i64 of microseconds, which is still about 292 thousand years
+    let micros = i64::try_from(parse_time(time)?.as_micros())
+        .map_err(|_| format!("{} is too far to jump", arg))?;
+    Ok(sign * micros)
+}
+
+/// Parses a positive number of seconds, which may have a fraction
+#[cfg(feature = "tui")]
+fn parse_seconds(arg: &str) -> Result<Duration, String> {
+    let secs = arg.parse::<f64>().map_err(|e| e.to_string())?;
+    if secs <= 0.0 || !secs.is_finite() {
+        return Err("has to be more than 0".to_string());
+    }
+    Duration::try_from_secs_f64(secs).map_err(|_| "is too long".to_string())
+}
+
 #[derive(Debug, Parser)]
 #[clap(author, about, version, long_about = None)]
 struct Args {
     /// Changes the service that the DBus commands are sent to
-    /// If changed, the play-song commands won't work, and the now-playing might not work
+    /// If changed, the play-song commands won't work (they refuse to unless --force is given),
+    /// and the now-playing might not work
     ///
-    #[clap(
-        short,
-        long,
-        value_parser,
-        default_value = "org.mpris.MediaPlayer2.spotify"
-    )]
-    service_name: String,
+    /// Defaults to $SPOTIFY_CONTROL_SERVICE, then `service_name` from the config file,
+    /// or org.mpris.MediaPlayer2.spotify if neither is set.
+    /// When Spotify isn't running, the first other MPRIS player that is found is used instead.
+    /// `vlc` is short for org.mpris.MediaPlayer2.vlc, and a * matches any part of a name, like
+    /// `chromium.*`. A player that isn't on the bus under its name is found under `<name>.<instance>`.
+    #[clap(short, long, value_parser)]
+    service_name: Option<String>,
+
+    /// Removes clutter like "(feat. X)" and "- Remastered 2011" from the names that are shown
+    #[clap(long, action)]
+    trim_feat: bool,
+
+    /// Only show the first few artists of a song, followed by "+N more"
+    #[clap(long, value_name = "N", value_parser = parse_max_artists)]
+    max_artists: Option<usize>,
+
+    /// Save the artwork of notifications to the cache directory and keep it, to check what the
+    /// notification should have shown
+    #[clap(long, action)]
+    keep_artwork: bool,
+
+    /// Leave artwork that's larger than this out of notifications, defaults to 5 MiB
+    #[clap(long, value_name = "BYTES")]
+    artwork_max_bytes: Option<u64>,
+
+    /// How often to try connecting to the bus again when it fails, defaults to 2
+    #[clap(long, value_name = "N")]
+    retries: Option<u32>,
+
+    /// Where searches are sent to. Defaults to `search_backend` from the config file, or spotify
+    /// when Web API credentials are configured and heroku otherwise.
+    #[cfg(feature = "search")]
+    #[clap(long, arg_enum, value_name = "BACKEND")]
+    search_backend: Option<SearchBackend>,
+
+    /// Print more about what's going on, to stderr
+    #[clap(short, long, action)]
+    verbose: bool,
+
+    /// Don't print messages like "Playing <song>". What a command is for, like the status from
+    /// play-pause or the song from now-playing, and errors are always printed
+    #[clap(short, long, action)]
+    quiet: bool,
+
+    /// When to color the output, `auto` leaves it out when `NO_COLOR` is set or it isn't a terminal
+    #[clap(long, arg_enum, value_name = "WHEN", default_value = "auto")]
+    color: ColorChoice,
+
+    /// How notifications are shown, try notify-send when they don't show up
+    #[clap(long, arg_enum, value_name = "BACKEND")]
+    // Without the notifications feature notify-send is the only way left to show them
+    #[cfg_attr(feature = "notifications", clap(default_value = "notify-rust"))]
+    #[cfg_attr(not(feature = "notifications"), clap(default_value = "notify-send"))]
+    notify_backend: NotifyBackend,
 
+    /// When there's none, `default_command` from the config file is used
     #[clap(subcommand)]
-    action: Commands,
+    action: Option<Commands>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct Response {
-    tracks: Tracks,
+/// How long to wait before trying to connect again
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// Connects to the session bus and builds a proxy for the player, trying again up to `retries`
+/// times. That helps when this runs early during login, before the bus is up.
+///
+/// The service is found with [`resolve_service`], when none is given Spotify is used, or the
+/// first MPRIS player on the bus when Spotify isn't running.
+async fn connect(
+    service: Option<String>,
+    retries: u32,
+    verbose: bool,
+    timeout: Duration,
+) -> Result<(Connection, PlayerProxy<'static>), Error> {
+    let mut attempt = 0;
+    loop {
+        let connected = tokio::time::timeout(timeout, try_connect(service.clone(), verbose));
+        let result = match connected.await {
+            Ok(result) => result,
+            Err(_) => Err(Error::TimedOut(Timeout::Dbus, timeout)),
+        };
+        match result {
+            Err(e) if attempt < retries => {
+                attempt += 1;
+                if verbose {
+                    eprintln!("Couldn't connect ({}), retry {}/{}", e, attempt, retries);
+                }
+                tokio::time::sleep(RETRY_DELAY).await;
+            }
+            result => return result,
+        }
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct Tracks {
-    items: Vec<Track>,
+async fn try_connect(
+    service: Option<String>,
+    verbose: bool,
+) -> Result<(Connection, PlayerProxy<'static>), Error> {
+    let conn = Connection::session().await?;
+
+    let requested = service.unwrap_or_default();
+    let service = resolve_service(&conn, &requested).await?;
+    if verbose && service != requested {
+        eprintln!("Using {}", service);
+    }
+
+    let proxy = PlayerProxy::builder(&conn)
+        .destination(service)?
+        .build()
+        .await?;
+    Ok((conn, proxy))
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-struct Track {
-    name: String,
-    id: String,
-    artists: Vec<Artist>,
-    album: Album,
+/// Finds the service on the bus that `requested` names, so every command picks the same one.
+/// $SPOTIFY_CONTROL_SERVICE was already applied by [`Config::load`], see [`resolve_name`] for the rest.
+async fn resolve_service(conn: &Connection, requested: &str) -> Result<String, Error> {
+    let names = fdo::DBusProxy::new(conn).await?.list_names().await?;
+    let names = names.iter().map(|name| name.as_str()).collect::<Vec<_>>();
+    resolve_name(&names, requested)
 }
 
-impl Display for Track {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let artists = self
-            .artists
-            .iter()
-            .map(|a| a.name.clone())
-            .collect::<Vec<_>>();
-        let (last, start) = artists.split_last().unwrap();
-        let artists = start.join(", ");
-        let artist = if artists.is_empty() {
-            last.to_string()
-        } else {
-            format!("{} and {}", artists, last)
-        };
-        write!(f, "{} by {} on {}", self.name, artist, self.album.name)
+/// Picks the name from `names` that `requested` means:
+/// - an empty name is detected like [`detect_service`]
+/// - a name that isn't on the bus can be short for an MPRIS player, `vlc` is
+///   `org.mpris.MediaPlayer2.vlc`
+/// - a `*` matches anything, `chromium.*` is the first Chromium tab
+/// - a name that isn't on the bus is the first instance of it that is, players like VLC add
+///   `.instance<pid>` to their name
+///
+/// A name nothing matches is returned as it is, so the error says that player isn't running,
+/// except for a pattern which has no name to use.
+fn resolve_name(names: &[&str], requested: &str) -> Result<String, Error> {
+    if requested.is_empty() {
+        return Ok(detect_service(names).to_string());
+    }
+    let player = match requested.starts_with(MPRIS_PREFIX) {
+        true => requested.to_string(),
+        false => format!("{}{}", MPRIS_PREFIX, requested),
+    };
+
+    // The name as it was given goes first, it can be a player that doesn't use MPRIS_PREFIX
+    for candidate in [requested, &player] {
+        if let Some(name) = find_service(names, candidate) {
+            return Ok(name.to_string());
+        }
+    }
+    match requested.contains('*') {
+        true => Err(Error::NoMatchingService(requested.to_string())),
+        // Without dots it can't be a name of its own
+        false if !requested.contains('.') => Ok(player),
+        false => Ok(requested.to_string()),
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-struct Artist {
-    name: String,
+/// The name on the bus that `pattern` matches, or the first instance of it
+fn find_service<'a>(names: &[&'a str], pattern: &str) -> Option<&'a str> {
+    if pattern.contains('*') {
+        return names
+            .iter()
+            .copied()
+            .find(|name| matches_pattern(pattern, name));
+    }
+    let instance = format!("{}.", pattern);
+    names
+        .iter()
+        .copied()
+        .find(|name| *name == pattern)
+        .or_else(|| {
+            names
+                .iter()
+                .copied()
+                .find(|name| name.starts_with(&instance))
+        })
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-struct Album {
-    name: String,
+/// Picks Spotify if it's on the bus, otherwise the first MPRIS player that is.
+fn detect_service<'a>(names: &[&'a str]) -> &'a str {
+    let players = names
+        .iter()
+        .copied()
+        .filter(|name| name.starts_with(MPRIS_PREFIX))
+        .collect::<Vec<_>>();
+    if players.is_empty() || players.contains(&DEFAULT_SERVICE) {
+        DEFAULT_SERVICE
+    } else {
+        players[0]
+    }
 }
 
-async fn search(query: &str) -> Vec<Track> {
-    let url = format!(
-        "https://spotify-search-api-test.herokuapp.com/search/tracks?track={}",
-        query.replace(' ', "%20")
-    );
-    let res: Response = reqwest::get(&url).await.unwrap().json().await.unwrap();
-    res.tracks.items
+/// Whether `name` matches `pattern`, in which a `*` matches any number of characters
+fn matches_pattern(pattern: &str, name: &str) -> bool {
+    let parts = pattern.split('*').collect::<Vec<_>>();
+    let (first, last) = (parts[0], parts[parts.len() - 1]);
+    if parts.len() == 1 {
+        return pattern == name;
+    }
+    if name.len() < first.len() + last.len() || !name.starts_with(first) || !name.ends_with(last) {
+        return false;
+    }
+    // The parts in between have to come in order, the first place each fits leaves the most room
+    let mut rest = &name[first.len()..name.len() - last.len()];
+    for part in &parts[1..parts.len() - 1] {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+    true
 }
 
-#[tokio::main]
-async fn main() {
-    let args = Args::parse();
+/// How long to wait before reading the metadata again
+const METADATA_RETRY_DELAY: Duration = Duration::from_millis(200);
 
-    let conn = zbus::Connection::session().await.unwrap();
+/// Everything the commands share, created once before running one
+struct Context {
+    config: Config,
+    options: DisplayOptions,
+    /// Shared by every HTTP request, so connections can be reused
+    #[cfg(feature = "http-client")]
+    client: reqwest::Client,
+    /// Only artwork for notifications is downloaded
+    #[cfg_attr(not(feature = "notifications"), allow(dead_code))]
+    keep_artwork: bool,
+    notify_backend: NotifyBackend,
+    /// Leave out informational messages on stdout, never the output a command is for
+    quiet: bool,
+    /// Report retries on stderr
+    verbose: bool,
+}
 
-    let proxy = PlayerProxy::builder(&conn)
-        .destination(args.service_name)
-        .unwrap()
-        .build()
-        .await
-        .unwrap();
+impl Context {
+    fn new(
+        config: Config,
+        keep_artwork: bool,
+        quiet: bool,
+        verbose: bool,
+        color: ColorChoice,
+        notify_backend: NotifyBackend,
+    ) -> Result<Context, Error> {
+        let mut options = DisplayOptions::from_config(&config)?;
+        options.color = color.enabled();
+        // Checked now so a wrong one is noticed, not just when it's needed
+        for kind in Timeout::ALL {
+            config.timeout(kind)?;
+        }
+        // Requests get the timeout of what they're for from `timed`, instead of one for all
+        #[cfg(feature = "http-client")]
+        let client = reqwest::Client::builder()
+            .user_agent(concat!("spotify-control/", env!("CARGO_PKG_VERSION")))
+            .build()?;
+        Ok(Context {
+            config,
+            options,
+            #[cfg(feature = "http-client")]
+            client,
+            keep_artwork,
+            notify_backend,
+            quiet,
+            verbose,
+        })
+    }
 
-    match args.action {
-        Commands::Next => proxy.next().await.unwrap(),
-        Commands::Previous => proxy.previous().await.unwrap(),
-        Commands::PlayPause => proxy.play_pause().await.unwrap(),
-        Commands::NowPlaying => what(proxy.metadata().await.unwrap().try_into().unwrap()).await,
-        Commands::PlaySong { mode } => play_song(&proxy, mode).await,
+    /// Runs `future`, giving up when it takes longer than the timeout for `kind`
+    async fn timed<T, E>(
+        &self,
+        kind: Timeout,
+        future: impl Future<Output = Result<T, E>>,
+    ) -> Result<T, Error>
+    where
+        Error: From<E>,
+    {
+        let timeout = self.config.timeout(kind)?;
+        match tokio::time::timeout(timeout, future).await {
+            Ok(result) => Ok(result?),
+            Err(_) => Err(Error::TimedOut(kind, timeout)),
+        }
+    }
+
+    /// Runs `future` that talks to the player with the DBus timeout. zbus doesn't give up on
+    /// method calls by itself, so a stuck player would otherwise be waited for forever.
+    async fn call<T, E>(&self, future: impl Future<Output = Result<T, E>>) -> Result<T, Error>
+    where
+        Error: From<E>,
+    {
+        self.timed(Timeout::Dbus, future).await
     }
-}
 
-async fn play_song<'proxy>(proxy: &PlayerProxy<'proxy>, mode: PlayMode) {
-    match mode {
-        PlayMode::Uri { uri } => proxy.open_uri(&uri).await.unwrap(),
-        PlayMode::Search { query, list, count } => {
-            let query = query.join(" ");
-            let track = search(&query).await;
-            if list {
-                for (i, track) in track.iter().take(count).enumerate() {
-                    println!("{} - {}", i, track);
+    /// Reads the metadata, trying again up to `metadata_retries` times when it fails. Reading it
+    /// can fail for a moment right after the song changes.
+    async fn metadata(&self, proxy: &PlayerProxy<'_>) -> Result<Metadata, Error> {
+        let retries = self.config.metadata_retries;
+        let mut attempt = 0;
+        loop {
+            match self.timed(Timeout::Dbus, metadata(proxy)).await {
+                Err(e) if attempt < retries => {
+                    attempt += 1;
+                    if self.verbose {
+                        eprintln!(
+                            "Couldn't read the metadata ({}), retry {}/{}",
+                            e, attempt, retries
+                        );
+                    }
+                    tokio::time::sleep(METADATA_RETRY_DELAY).await;
                 }
-                print!("Enter a number to play: ");
-                std::io::stdout().flush().unwrap();
-                let mut input = String::new();
-                std::io::stdin().read_line(&mut input).unwrap();
-                let input = input.trim().parse::<usize>().unwrap();
-                let track = track.get(input).unwrap();
-                println!("Playing {}", track);
-                let uri = format!("spotify:track:{}", track.id);
-                proxy.open_uri(&uri).await.unwrap()
-            } else if let Some(track) = track.first() {
-                println!("Playing {}", track);
-                let uri = format!("spotify:track:{}", track.id);
-                proxy.open_uri(&uri).await.unwrap()
-            } else {
-                println!("No track found for {}", query);
+                result => return result,
             }
         }
     }
+
+    /// The artwork of the song, in the configured size if there is one
+    fn artwork_url(&self, metadata: &Metadata) -> String {
+        match self.config.artwork_size {
+            Some(size) => display::sized_artwork(&metadata.artwork, size),
+            None => metadata.artwork.clone(),
+        }
+    }
 }
 
-async fn what(metadata: Metadata) {
-    let res = reqwest::get(&metadata.artwork).await.unwrap();
-    let bytes = res.bytes().await.unwrap();
-    let tmp = temp_file::with_contents(&bytes);
+#[tokio::main]
+async fn main() {
+    let args = Args::parse();
 
-    let _not = Notification::new()
-        .appname("Spotify Notify")
-        .summary(&metadata.title)
-        .body(&format!(
+    match run(args).await {
+        Ok(()) => {}
+        // Whatever reads the output went away, like `head` does once it has enough
+        Err(Error::IoError(e)) if e.kind() == std::io::ErrorKind::BrokenPipe => {}
+        #[cfg(feature = "search")]
+        Err(e @ Error::NotFoundPrinted) => std::process::exit(e.exit_code()),
+        // Like clap does for a missing subcommand, which is a usage error
+        Err(e @ Error::NoCommand) => {
+            let _ = Args::command().write_help(&mut std::io::stderr());
+            std::process::exit(e.exit_code());
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(e.exit_code());
+        }
+    }
+}
+
+/// Runs the commands that don't need the config file, `false` for the others
+fn without_config(command: &Commands, verbose: bool) -> Result<bool, Error> {
+    match command {
+        Commands::Config {
+            action: Some(ConfigAction::Init { force }),
+            ..
+        } => {
+            let path = Config::init(*force)?;
+            println!("Wrote the default config to {}", path.display());
+        }
+        Commands::Version { verbose: details } => {
+            println!("spotify-control {}", env!("CARGO_PKG_VERSION"));
+            if *details || verbose {
+                println!("commit:   {}", env!("GIT_COMMIT"));
+                match FEATURES {
+                    [] => println!("features: none"),
+                    features => println!("features: {}", features.join(", ")),
+                }
+                println!("zbus:     {}", env!("ZBUS_VERSION"));
+                #[cfg(feature = "http-client")]
+                println!("reqwest:  {}", env!("REQWEST_VERSION"));
+            }
+        }
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+/// Parses `default_command` from the config file, or prints the help when there is none
+fn default_command(config: &Config) -> Result<Commands, Error> {
+    let words = config.default_command.as_deref().unwrap_or("");
+    if words.trim().is_empty() {
+        return Err(Error::NoCommand);
+    }
+    let words = split_words(words).map_err(Error::InvalidDefaultCommand)?;
+    // Flags for the whole program would be parsed and then ignored, so they're refused
+    if let Some(flag) = words.first().filter(|word| word.starts_with('-')) {
+        return Err(Error::InvalidDefaultCommand(format!(
+            "it has to start with a command, {} can only be given on the command line",
+            flag
+        )));
+    }
+    let command = Commands::augment_subcommands(
+        clap::Command::new("default_command")
+            .no_binary_name(true)
+            .subcommand_required(true),
+    );
+    command
+        .try_get_matches_from(&words)
+        .and_then(|matches| Commands::from_arg_matches(&matches))
+        .map_err(|e| {
+            // Only the message, the usage clap adds is about the command line
+            let e = e.to_string();
+            let message = e.lines().next().unwrap_or_default();
+            Error::InvalidDefaultCommand(message.trim_start_matches("error: ").to_string())
+        })
+}
+
+/// Splits a line into words like a shell does, so `now-playing --format "{title} - {artist}"`
+/// keeps the format together. Single quotes keep everything as is, in double quotes and outside
+/// of quotes a backslash keeps the next character as is.
+fn split_words(line: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    // None between words, so "" can still be an empty word
+    let mut word: Option<String> = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err("it has a ' that isn't closed".to_string()),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c) => word.push(c),
+                            None => return Err("it ends with a \\".to_string()),
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err("it has a \" that isn't closed".to_string()),
+                    }
+                }
+            }
+            '\\' => match chars.next() {
+                Some(c) => word.get_or_insert_with(String::new).push(c),
+                None => return Err("it ends with a \\".to_string()),
+            },
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+async fn run(args: Args) -> Result<(), Error> {
+    // Checked before the config file is read, so a broken one can still be replaced
+    if let Some(command) = &args.action {
+        if without_config(command, args.verbose)? {
+            return Ok(());
+        }
+    }
+
+    let mut config = Config::load()?;
+    if let Some(service) = args.service_name {
+        config.service_name = Some(service);
+    }
+    if args.trim_feat {
+        config.trim_feat = true;
+    }
+    if let Some(max) = args.max_artists {
+        config.max_artists = Some(max);
+    }
+    if let Some(max) = args.artwork_max_bytes {
+        config.artwork_max_bytes = max;
+    }
+    if let Some(retries) = args.retries {
+        config.retries = retries;
+    }
+    #[cfg(feature = "search")]
+    if let Some(backend) = args.search_backend {
+        config.search_backend = Some(backend);
+    }
+
+    let command = match args.action {
+        Some(command) => command,
+        None => default_command(&config)?,
+    };
+
+    // The default command can be one of them too
+    if without_config(&command, args.verbose)? {
+        return Ok(());
+    }
+
+    if let Commands::Config { json, .. } = command {
+        if json {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&config.redacted()).unwrap()
+            );
+        } else {
+            print!("{}", toml::to_string(&config.redacted()).unwrap());
+        }
+        return Ok(());
+    }
+
+    if command == Commands::DumpBusNames {
+        let conn = Connection::session().await?;
+        let names = fdo::DBusProxy::new(&conn).await?.list_names().await?;
+        let mut names = names.iter().map(|name| name.as_str()).collect::<Vec<_>>();
+        names.sort_unstable();
+        for name in names {
+            println!("{}", name);
+        }
+        return Ok(());
+    }
+
+    if let Commands::NowPlaying {
+        artwork_size: Some(size),
+        ..
+    }
+    | Commands::ArtworkUrl { size: Some(size) } = command
+    {
+        config.artwork_size = Some(size);
+    }
+
+    let ctx = Context::new(
+        config,
+        args.keep_artwork,
+        args.quiet,
+        args.verbose,
+        args.color,
+        args.notify_backend,
+    )?;
+    // These only use the Web API, so Spotify doesn't have to run here
+    #[cfg(feature = "web-api")]
+    match &command {
+        Commands::Auth => return auth(&ctx).await,
+        Commands::Play {
+            device: Some(device),
+            ..
+        } => return play_on_device(&ctx, device).await,
+        _ => {}
+    }
+
+    // Searching and picking don't need the player, so `--resolve-only` works without one
+    #[cfg(feature = "search")]
+    let found = match &command {
+        Commands::PlaySong {
+            mode: PlayMode::Uri { .. },
+            ..
+        } => None,
+        Commands::PlaySong { mode, args } => {
+            let (track, resolve_only) = find(&ctx, mode, args).await?;
+            if resolve_only {
+                return resolve(&track, args);
+            }
+            Some(track)
+        }
+        _ => None,
+    };
+
+    let config = &ctx.config;
+    let (_conn, proxy) = connect(
+        config.service_name.clone(),
+        config.retries,
+        args.verbose,
+        config.timeout(Timeout::Dbus)?,
+    )
+    .await?;
+    let options = &ctx.options;
+
+    match command {
+        Commands::Next { count, control } => {
+            self::control(&proxy, &ctx, Control::Next(count), control).await?
+        }
+        Commands::Previous { count, control } => {
+            self::control(&proxy, &ctx, Control::Previous(count), control).await?
+        }
+        Commands::Play { control, .. } => {
+            self::control(&proxy, &ctx, Control::Play, control).await?
+        }
+        Commands::Pause { control } => self::control(&proxy, &ctx, Control::Pause, control).await?,
+        Commands::PlayPause { control } => {
+            let status = settle(&proxy, &ctx, Control::PlayPause).await?;
+            if !control.json {
+                // Lowercase so scripts can compare it without caring about the player
+                println!("{}", status.to_lowercase());
+            }
+            report(&proxy, &ctx, &status, &control).await?;
+        }
+        Commands::Stop { control } => self::control(&proxy, &ctx, Control::Stop, control).await?,
+        #[cfg(feature = "web-api")]
+        Commands::Like { unlike, toggle } => like(&proxy, &ctx, unlike, toggle).await?,
+        #[cfg(feature = "web-api")]
+        Commands::Saved { icon } => {
+            let metadata = ctx.metadata(&proxy).await?;
+            let id = metadata.spotify_id().ok_or(Error::NoSpotifyTrack)?;
+            let saved = web_api::saved(&ctx.client, &ctx.config, id);
+            let saved = ctx.timed(Timeout::Web, saved).await?;
+            match icon {
+                true => println!("{}", if saved { "♥" } else { "♡" }),
+                false => println!("{}", saved),
+            }
+        }
+        Commands::Raise { force } => application(&proxy, &ctx, Application::Raise, force).await?,
+        Commands::Quit { force } => application(&proxy, &ctx, Application::Quit, force).await?,
+        Commands::NowPlaying {
+            format: Some(format),
+            duration_style,
+            ..
+        } => println!(
+            "{}",
+            now_playing(&proxy, &ctx, &format, duration_style).await?
+        ),
+        Commands::NowPlaying { format: None, .. } => what(&ctx, ctx.metadata(&proxy).await?).await,
+        #[cfg(feature = "tui")]
+        Commands::Info {
+            watch: true,
+            interval,
+        } => dashboard(&proxy, &ctx, interval).await?,
+        Commands::Info { .. } => println!("{}", ctx.call(info(&proxy, options)).await?),
+        Commands::ArtworkUrl { .. } => {
+            let metadata = ctx.call(metadata(&proxy)).await?;
+            if metadata.artwork.is_empty() {
+                return Err(Error::NoArtwork);
+            }
+            println!("{}", ctx.artwork_url(&metadata));
+        }
+        Commands::Metadata { follow: true, .. } => follow_metadata(&proxy, &ctx).await?,
+        Commands::Metadata { json, .. } => {
+            let metadata = ctx.call(metadata(&proxy)).await?;
+            if json {
+                println!("{}", serde_json::to_string(&metadata).unwrap());
+            } else {
+                println!("Title:   {}", metadata.title);
+                println!("Artists: {}", metadata.artists.join(", "));
+                println!("Album:   {}", metadata.album);
+                println!("Artwork: {}", metadata.artwork);
+                println!("Trackid: {}", metadata.trackid);
+                println!("Url:     {}", metadata.url);
+                if let Some(length) = metadata.length {
+                    let length = Duration::from_micros(length.max(0) as u64);
+                    println!("Length:  {}", format_duration(length));
+                }
+            }
+        }
+        Commands::PlaySong {
+            mode: PlayMode::Uri { uri },
+            args,
+        } => open_uri(&proxy, &ctx, &uri, &args).await?,
+        #[cfg(feature = "search")]
+        Commands::PlaySong { args, .. } => {
+            let track = found.expect("searched for before connecting");
+            play_found(&proxy, &ctx, &track, &args).await?
+        }
+        Commands::Watch {
+            no_notify,
+            exec,
+            json,
+        } => {
+            let notify = !no_notify && !json;
+            watch(&proxy, &ctx, notify, exec, json).await?
+        }
+        Commands::Seek { to: Some(to), .. } => ctx.call(seek_to(&proxy, to)).await?,
+        Commands::Seek { by: Some(by), .. } => ctx.call(proxy.seek(by)).await?,
+        Commands::Seek { .. } => unreachable!("clap requires either --to or --by"),
+        Commands::Set {
+            property: SetProperty::Rate { rate, clamp },
+        } => ctx.call(set_rate(&proxy, rate, clamp)).await?,
+        Commands::Set {
+            property:
+                SetProperty::Volume {
+                    volume: Some(volume),
+                    ..
+                },
+        } => ctx.call(set_volume(&proxy, volume)).await?,
+        Commands::Set {
+            property:
+                SetProperty::Volume {
+                    relative: Some(delta),
+                    ..
+                },
+        } => println!("{:.2}", ctx.call(change_volume(&proxy, delta)).await?),
+        Commands::Set {
+            property: SetProperty::Volume { .. },
+        } => unreachable!("clap requires either a volume or --relative"),
+        #[cfg(feature = "http-server")]
+        Commands::Http { port, bind } => {
+            let addr = std::net::SocketAddr::new(bind, port);
+            server::serve(proxy.clone(), std::sync::Arc::new(ctx), addr).await?
+        }
+        Commands::Config { .. } | Commands::Version { .. } | Commands::DumpBusNames => {
+            unreachable!("handled before connecting")
+        }
+        #[cfg(feature = "web-api")]
+        Commands::Auth => unreachable!("handled before connecting"),
+    }
+    Ok(())
+}
+
+/// Finds the song a search or `last` picks, and whether it's only resolved instead of played
+#[cfg(feature = "search")]
+async fn find(ctx: &Context, mode: &PlayMode, args: &PlayArgs) -> Result<(Track, bool), Error> {
+    let options = &ctx.options;
+    let tracks;
+    let (track, resolve_only) = match mode {
+        PlayMode::Uri { .. } => unreachable!("opened without searching"),
+        PlayMode::Search {
+            query,
+            list,
+            select,
+            resolve_only,
+            page,
+            sort,
+        } => {
+            let query = search::join_query(query);
+            let search = search::search(&ctx.client, &ctx.config, &query);
+            let mut found = ctx.timed(Timeout::Search, search).await?;
+            if found.is_empty() {
+                cache::clear();
+                if args.control.json {
+                    let error = serde_json::json!({ "error": "not_found", "query": query });
+                    println!("{}", error);
+                    return Err(Error::NotFoundPrinted);
+                }
+                return Err(Error::NotFound(query));
+            }
+            search::sort(&mut found, *sort);
+            tracks = page.skip(found)?;
+            let track = if *list {
+                let shown = page.shown(&tracks, ctx.config.count);
+                // Saved before asking, so one can also look now and pick later with `last`
+                if let Err(e) = cache::save(shown) {
+                    eprintln!("Couldn't remember the songs for play-song last: {}", e);
+                }
+                pick(shown, options, std::io::stdin().lock())?
+            } else {
+                cache::clear();
+                if let Some(index) = *select {
+                    tracks.get(index).ok_or(Error::NoSuchResult {
+                        index,
+                        found: tracks.len(),
+                    })?
+                } else {
+                    &tracks[0]
+                }
+            };
+            (track, *resolve_only)
+        }
+        PlayMode::Last {
+            select,
+            page,
+            resolve_only,
+        } => {
+            tracks = page.skip(cache::load()?)?;
+            let track = match *select {
+                Some(index) => tracks.get(index).ok_or(Error::NoSuchResult {
+                    index,
+                    found: tracks.len(),
+                })?,
+                None => pick(
+                    page.shown(&tracks, tracks.len()),
+                    options,
+                    std::io::stdin().lock(),
+                )?,
+            };
+            (track, *resolve_only)
+        }
+    };
+    Ok((track.clone(), resolve_only))
+}
+
+/// Prints the song as JSON with the uri that would be played, for `--resolve-only`
+#[cfg(feature = "search")]
+fn resolve(track: &Track, args: &PlayArgs) -> Result<(), Error> {
+    let resolved = ResolvedTrack {
+        track,
+        uri: track.uri(),
+    };
+    println!("{}", serde_json::to_string(&resolved).unwrap());
+    write_uri(args, &resolved.uri)
+}
+
+/// Plays the song a search or `last` picked
+#[cfg(feature = "search")]
+async fn play_found(
+    proxy: &PlayerProxy<'_>,
+    ctx: &Context,
+    track: &Track,
+    args: &PlayArgs,
+) -> Result<(), Error> {
+    if !ctx.quiet {
+        println!("Playing {}", track.display(&ctx.options));
+    }
+    open_uri(proxy, ctx, &track.uri(), args).await
+}
+
+/// Gets a refresh token for the Web API with the user's help
+#[cfg(feature = "web-api")]
+async fn auth(ctx: &Context) -> Result<(), Error> {
+    let url = web_api::authorize_url(&ctx.config)?;
+    println!(
+        "Add {} as a redirect URI of your app on https://developer.spotify.com/dashboard, then open:",
+        web_api::REDIRECT_URI
+    );
+    println!("{}", url);
+    let input = prompt(
+        "Paste the address it takes you to (it doesn't have to load): ",
+        std::io::stdin().lock(),
+    )?;
+    // Just the code works too
+    let code = reqwest::Url::parse(&input)
+        .ok()
+        .and_then(|url| {
+            url.query_pairs()
+                .find(|(key, _)| key == "code")
+                .map(|(_, code)| code.into_owned())
+        })
+        .unwrap_or(input);
+    let token = web_api::refresh_token(&ctx.client, &ctx.config, &code);
+    let token = ctx.timed(Timeout::Web, token).await?;
+    println!("Add this to the config file:");
+    println!("refresh_token = \"{}\"", token);
+    Ok(())
+}
+
+/// Starts playing on a Spotify Connect device, found by its name
+#[cfg(feature = "web-api")]
+async fn play_on_device(ctx: &Context, name: &str) -> Result<(), Error> {
+    let name = match name {
+        "default" => ctx
+            .config
+            .default_device
+            .as_deref()
+            .ok_or(Error::NoDefaultDevice)?,
+        name => name,
+    };
+    let devices = web_api::devices(&ctx.client, &ctx.config);
+    let devices = ctx.timed(Timeout::Web, devices).await?;
+    let id = devices
+        .iter()
+        .find(|device| device.name.eq_ignore_ascii_case(name))
+        .and_then(|device| device.id.as_deref())
+        .ok_or_else(|| Error::DeviceOffline(name.to_string()))?;
+    ctx.timed(Timeout::Web, web_api::play_on(&ctx.client, &ctx.config, id))
+        .await
+}
+
+/// Lists the songs and asks which one to play
+#[cfg(feature = "search")]
+fn pick<'a>(
+    shown: &'a [Track],
+    options: &DisplayOptions,
+    answers: impl std::io::BufRead,
+) -> Result<&'a Track, Error> {
+    // The page always has a song, but there's no range to ask for without one
+    let last = shown
+        .len()
+        .checked_sub(1)
+        .ok_or(Error::NoSuchResult { index: 0, found: 0 })?;
+    for (i, track) in shown.iter().enumerate() {
+        let i = options.paint(&i.to_string(), Style::Bold);
+        match track.duration() {
+            Some(duration) => {
+                let duration = format!("[{}]", format_duration(duration));
+                println!(
+                    "{} - {} {}",
+                    i,
+                    track.display(options),
+                    options.paint(&duration, Style::Dim)
+                )
+            }
+            None => println!("{} - {}", i, track.display(options)),
+        }
+    }
+    let question = format!("Enter a number to play (0-{}): ", last);
+    let input = prompt(&question, answers)?;
+    input
+        .parse::<usize>()
+        .ok()
+        .and_then(|i| shown.get(i))
+        .ok_or(Error::InvalidSelection(input))
+}
+
+/// Opens a uri, refusing spotify: uris when the player isn't Spotify unless `--force` is used.
+/// With `--notify` the song that starts playing is shown afterwards.
+async fn open_uri(
+    proxy: &PlayerProxy<'_>,
+    ctx: &Context,
+    uri: &str,
+    args: &PlayArgs,
+) -> Result<(), Error> {
+    let service = proxy.inner().destination().to_string();
+    // Spotify registers an extra name with an instance suffix when more than one is running
+    let spotify =
+        service == DEFAULT_SERVICE || service.starts_with(&format!("{}.", DEFAULT_SERVICE));
+    if !spotify && uri.starts_with("spotify:") {
+        if !args.force {
+            return Err(Error::NotSpotify(service));
+        }
+        eprintln!("{} isn't Spotify, trying to open {} anyway", service, uri);
+    }
+    if !args.control.notify && !args.control.json && !args.verify {
+        ctx.call(proxy.open_uri(uri)).await?;
+        return write_uri(args, uri);
+    }
+    let previous = ctx.call(metadata(proxy)).await;
+    let previous = previous.map(|m| m.trackid).unwrap_or_default();
+    ctx.call(proxy.open_uri(uri)).await?;
+    write_uri(args, uri)?;
+    let playing = new_track(proxy, ctx, &previous).await?;
+    // Only track uris can be compared, albums and artists start with any of their songs
+    let comparable = uri.starts_with("spotify:track:") || !uri.starts_with("spotify:");
+    if args.verify && comparable && !playing.is(uri) {
+        eprintln!(
+            "{} didn't start playing {}, it's playing {} instead",
+            service, uri, playing.title
+        );
+    }
+    if args.control.json {
+        print_event(&playing, &ctx.call(proxy.playback_status()).await?)?;
+    }
+    if args.control.notify {
+        what(ctx, playing).await;
+    }
+    Ok(())
+}
+
+/// Writes the uri to the `--write-uri` file if there is one, on a line of its own
+fn write_uri(args: &PlayArgs, uri: &str) -> Result<(), Error> {
+    if let Some(path) = &args.write_uri {
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(args.append)
+            .truncate(!args.append)
+            .open(path)?;
+        writeln!(file, "{}", uri)?;
+    }
+    Ok(())
+}
+
+/// How long to wait for the player to switch to an opened song
+const OPEN_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Waits until the player reports another song than `previous` and returns it, Spotify keeps
+/// reporting the old song for a moment after opening a uri. Gives up after [`OPEN_TIMEOUT`] and
+/// returns whatever is playing then, which happens when the song was already playing.
+async fn new_track(
+    proxy: &PlayerProxy<'_>,
+    ctx: &Context,
+    previous: &str,
+) -> Result<Metadata, Error> {
+    let poll = async {
+        loop {
+            match ctx.call(metadata(proxy)).await {
+                Ok(metadata) if metadata.trackid != previous => return Ok(metadata),
+                // There could be a moment without any song while switching
+                Ok(_) | Err(Error::MetadataError(_)) => {}
+                Err(e) => return Err(e),
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    };
+    match tokio::time::timeout(OPEN_TIMEOUT, poll).await {
+        Ok(metadata) => metadata,
+        Err(_) => ctx.call(metadata(proxy)).await,
+    }
+}
+
+/// Asks for a line of input from `answers` (stdin, outside of tests), fails with
+/// `Error::Cancelled` when it is closed
+#[cfg(any(feature = "search", feature = "web-api"))]
+fn prompt(question: &str, mut answers: impl std::io::BufRead) -> Result<String, Error> {
+    print!("{}", question);
+    std::io::stdout().flush()?;
+    let mut input = String::new();
+    if answers.read_line(&mut input)? == 0 {
+        // Print the newline the user didn't type, so the error ends up on its own line
+        println!();
+        return Err(Error::Cancelled);
+    }
+    Ok(input.trim().to_string())
+}
+
+/// The player methods behind the control commands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Control {
+    /// Skips this many songs
+    Next(u32),
+    Previous(u32),
+    Play,
+    Pause,
+    PlayPause,
+    Stop,
+}
+
+impl Control {
+    /// How many songs this skips, 0 when it changes the playback status instead
+    fn skips(self) -> u32 {
+        match self {
+            Control::Next(count) | Control::Previous(count) => count,
+            _ => 0,
+        }
+    }
+
+    async fn call(self, proxy: &PlayerProxy<'_>, ctx: &Context) -> Result<(), Error> {
+        match self {
+            Control::Next(count) => skip(count, || ctx.call(proxy.next())).await,
+            Control::Previous(count) => skip(count, || ctx.call(proxy.previous())).await,
+            Control::Play => ctx.call(proxy.play()).await,
+            Control::Pause => ctx.call(proxy.pause()).await,
+            Control::PlayPause => ctx.call(proxy.play_pause()).await,
+            Control::Stop => ctx.call(proxy.stop()).await,
+        }
+    }
+}
+
+/// The most songs `next` and `previous` skip at once
+const MAX_SKIPS: u32 = 20;
+/// How long to wait between skips, Spotify ignores skips that come in too quickly
+const SKIP_DELAY: Duration = Duration::from_millis(200);
+
+/// Calls `next` or `previous` `count` times
+async fn skip<F, Fut>(count: u32, call: F) -> Result<(), Error>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<(), Error>>,
+{
+    for done in 0..count {
+        if done > 0 {
+            tokio::time::sleep(SKIP_DELAY).await;
+        }
+        if let Err(error) = call().await {
+            return Err(match done {
+                0 => error,
+                _ => Error::PartialSkip {
+                    done,
+                    count,
+                    error: Box::new(error),
+                },
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Runs a control command, and shows the new state and song with `--notify` and `--json`
+async fn control(
+    proxy: &PlayerProxy<'_>,
+    ctx: &Context,
+    control: Control,
+    args: ControlArgs,
+) -> Result<(), Error> {
+    if !args.notify && !args.json {
+        return control.call(proxy, ctx).await;
+    }
+    let status = settle(proxy, ctx, control).await?;
+    report(proxy, ctx, &status, &args).await
+}
+
+/// Prints and shows the state after a control command, as `--json` and `--notify` ask
+async fn report(
+    proxy: &PlayerProxy<'_>,
+    ctx: &Context,
+    status: &str,
+    args: &ControlArgs,
+) -> Result<(), Error> {
+    if args.json {
+        match ctx.metadata(proxy).await {
+            Ok(metadata) => print_event(&metadata, status)?,
+            // Like after stopping, when some players don't have a song anymore
+            Err(Error::MetadataError(_)) => println!("{}", serde_json::json!({ "status": status })),
+            Err(e) => return Err(e),
+        }
+    }
+    if args.notify {
+        notify_state(proxy, ctx, status).await?;
+    }
+    Ok(())
+}
+
+/// Shows a notification with the playback status and the current song
+async fn notify_state(proxy: &PlayerProxy<'_>, ctx: &Context, status: &str) -> Result<(), Error> {
+    let metadata = ctx.call(metadata(proxy)).await?;
+    let options = &ctx.options;
+    let body = match metadata.artists.is_empty() {
+        true => options.name(&metadata.title),
+        false => format!(
             "{} - {}",
-            metadata.artists.join(", "),
-            metadata.album
-        ))
-        .image_path(tmp.path().to_str().unwrap())
-        .hint(Hint::Category("music".to_string()))
-        .show()
+            options.name(&metadata.title),
+            options.artists(&metadata.artists)
+        ),
+    };
+    show_notification(ctx, status, &body, "").await;
+    Ok(())
+}
+
+/// Saves the current song in the library or removes it from there, and prints which it is now
+#[cfg(feature = "web-api")]
+async fn like(
+    proxy: &PlayerProxy<'_>,
+    ctx: &Context,
+    unlike: bool,
+    toggle: bool,
+) -> Result<(), Error> {
+    let metadata = ctx.metadata(proxy).await?;
+    let id = metadata.spotify_id().ok_or(Error::NoSpotifyTrack)?;
+    let saved = match toggle {
+        true => {
+            let saved = web_api::saved(&ctx.client, &ctx.config, id);
+            !ctx.timed(Timeout::Web, saved).await?
+        }
+        false => !unlike,
+    };
+    let set_saved = web_api::set_saved(&ctx.client, &ctx.config, id, saved);
+    ctx.timed(Timeout::Web, set_saved).await?;
+    if !ctx.quiet {
+        let title = ctx.options.name(&metadata.title);
+        match saved {
+            true => println!("Saved {} in your library", title),
+            false => println!("Removed {} from your library", title),
+        }
+    }
+    Ok(())
+}
+
+/// What [`application`] asks the player to do
+#[derive(Debug, Clone, Copy)]
+enum Application {
+    Raise,
+    Quit,
+}
+
+/// Raises or quits the player. Players that say they can't are skipped unless `force` is set,
+/// since they'd silently do nothing. Either way it's printed whether it was attempted.
+async fn application(
+    proxy: &PlayerProxy<'_>,
+    ctx: &Context,
+    action: Application,
+    force: bool,
+) -> Result<(), Error> {
+    let inner = proxy.inner();
+    let player = MediaPlayer2Proxy::builder(inner.connection())
+        .destination(inner.destination().to_owned())?
+        .build();
+    let player = ctx.call(player).await?;
+    let service = inner.destination();
+    let (can, name) = match action {
+        Application::Raise => (ctx.call(player.can_raise()).await?, "raise"),
+        Application::Quit => (ctx.call(player.can_quit()).await?, "quit"),
+    };
+    if !can && !force {
+        eprintln!(
+            "{} says it can't {}, not trying (use --force to try anyway)",
+            service, name
+        );
+        return Ok(());
+    }
+    match action {
+        Application::Raise => ctx.call(player.raise()).await?,
+        Application::Quit => ctx.call(player.quit()).await?,
+    }
+    if !ctx.quiet {
+        println!("Asked {} to {}", service, name);
+    }
+    Ok(())
+}
+
+/// Runs a control command and returns the new playback status, after giving the player a moment
+/// to report the change it made
+async fn settle(proxy: &PlayerProxy<'_>, ctx: &Context, control: Control) -> Result<String, Error> {
+    // Reading the properties first makes sure the changes below are tracked
+    ctx.call(proxy.playback_status()).await?;
+    let before = Metadata::try_from(ctx.call(proxy.metadata()).await?).ok();
+    let before = before.map(|m| m.trackid);
+    let mut status = proxy.receive_playback_status_changed().await;
+    let mut changes = proxy.receive_metadata_changed().await;
+    control.call(proxy, ctx).await?;
+    // Spotify only reports the new status shortly after the call returns
+    let changed = async {
+        if control.skips() == 0 {
+            status.next().await;
+            return;
+        }
+        // Changes that come in quickly are reported as one, so the song is compared instead of
+        // counting them. The metadata is read from the cache of `proxy`, which the changes update.
+        while metadata(proxy).await.ok().map(|m| m.trackid) == before {
+            if changes.next().await.is_none() {
+                return;
+            }
+        }
+    };
+    let _ = tokio::time::timeout(Duration::from_secs(1), changed).await;
+    ctx.call(proxy.playback_status()).await
+}
+
+/// Builds the block of text shown by `info`, leaving out what the player doesn't support
+async fn info(proxy: &PlayerProxy<'_>, options: &DisplayOptions) -> Result<String, Error> {
+    let state = state::state(proxy).await?;
+
+    let status = options.paint(&state.status, Style::status(&state.status));
+    let mut lines = vec![("Status", status)];
+    if let Some(metadata) = &state.metadata {
+        let mut track = options.name(&metadata.title);
+        if !metadata.artists.is_empty() {
+            track.push_str(&format!(" by {}", options.artists(&metadata.artists)));
+        }
+        if !metadata.album.is_empty() {
+            track.push_str(&format!(" on {}", options.name(&metadata.album)));
+        }
+        lines.push(("Track", track));
+    }
+    if let Some(position) = state.position {
+        lines.push((
+            "Position",
+            match state.length {
+                Some(length) => format!(
+                    "{} / {} {}",
+                    format_duration(position),
+                    format_duration(length),
+                    progress_bar(position, length, 20)
+                ),
+                None => format_duration(position),
+            },
+        ));
+    }
+    if let Some(volume) = state.volume {
+        lines.push(("Volume", format!("{:.0}%", volume * 100.0)));
+    }
+    if let Some(shuffle) = state.shuffle {
+        lines.push(("Shuffle", if shuffle { "on" } else { "off" }.to_string()));
+    }
+    if let Some(loop_status) = state.loop_status {
+        lines.push(("Repeat", loop_status));
+    }
+    let lines = lines
+        .into_iter()
+        .map(|(label, value)| {
+            let label = options.paint(&format!("{:<9}", format!("{}:", label)), Style::Bold);
+            format!("{} {}", label, value)
+        })
+        .collect::<Vec<_>>();
+    Ok(lines.join("\n"))
+}
+
+/// Keeps redrawing the info in place, every `interval` and whenever the song or status changes,
+/// until ctrl-c is pressed
+#[cfg(feature = "tui")]
+async fn dashboard(
+    proxy: &PlayerProxy<'_>,
+    ctx: &Context,
+    interval: Duration,
+) -> Result<(), Error> {
+    let mut changes = proxy.receive_metadata_changed().await;
+    let mut status_changes = proxy.receive_playback_status_changed().await;
+    let mut ticks = tokio::time::interval(interval);
+    // Waited for across redraws, instead of starting to wait again for each one
+    let ctrl_c = tokio::signal::ctrl_c();
+    tokio::pin!(ctrl_c);
+
+    // Hide the cursor while drawing, it's shown again below however this stops
+    let result = loop {
+        let drawn = match ctx.call(info(proxy, &ctx.options)).await {
+            // Move to the top left and clear the screen before drawing
+            Ok(info) => {
+                let mut stdout = std::io::stdout().lock();
+                writeln!(stdout, "\x1b[?25l\x1b[H\x1b[2J{}", info)
+                    .and_then(|()| stdout.flush())
+                    .map_err(Error::from)
+            }
+            Err(e) => Err(e),
+        };
+        if let Err(e) = drawn {
+            break Err(e);
+        }
+
+        tokio::select! {
+            _ = &mut ctrl_c => break Ok(()),
+            _ = ticks.tick() => {}
+            Some(_) = changes.next() => {}
+            Some(_) = status_changes.next() => {}
+        }
+    };
+    let mut stdout = std::io::stdout().lock();
+    let shown = write!(stdout, "\x1b[?25h").and_then(|()| stdout.flush());
+    // The error that stopped it is the more interesting one
+    result.and(shown.map_err(Error::from))
+}
+
+/// Renders the `now-playing --format` template. The status and position are only
+/// read when the template uses them, to keep the common case fast.
+async fn now_playing(
+    proxy: &PlayerProxy<'_>,
+    ctx: &Context,
+    format: &str,
+    style: DurationStyle,
+) -> Result<String, Error> {
+    let options = &ctx.options;
+    let metadata = ctx.metadata(proxy).await?;
+    let status = if format.contains("{status}") {
+        Some(ctx.call(proxy.playback_status()).await?)
+    } else {
+        None
+    };
+    let position = if format.contains("{position}") || format.contains("{percent}") {
+        let position = ctx.call(proxy.position()).await?;
+        Some(Duration::from_micros(position.max(0) as u64))
+    } else {
+        None
+    };
+    let length = metadata
+        .length
+        .map(|length| Duration::from_micros(length.max(0) as u64));
+
+    Ok(render(format, |token| match token {
+        "title" => Some(options.name(&metadata.title)),
+        "artist" => Some(options.artists(&metadata.artists)),
+        "album" => Some(options.name(&metadata.album)),
+        "artwork" => Some(ctx.artwork_url(&metadata)),
+        "url" => Some(metadata.url.clone()),
+        "trackid" => Some(metadata.trackid.clone()),
+        "status" => status.clone(),
+        "position" => position.map(|position| style.format(position)),
+        "length" => length.map(|length| style.format(length)),
+        "percent" => match (position, length) {
+            (Some(position), Some(length)) if !length.is_zero() => Some(format!(
+                "{:.0}",
+                position.as_secs_f64() / length.as_secs_f64() * 100.0
+            )),
+            _ => None,
+        },
+        _ => None,
+    }))
+}
+
+/// Handles every song change until the player goes away, starting with the current song
+async fn watch(
+    proxy: &PlayerProxy<'_>,
+    ctx: &Context,
+    notify: bool,
+    exec: Option<String>,
+    json: bool,
+) -> Result<(), Error> {
+    let mut songs = songs(proxy, ctx).await?;
+    let mut status_changes = proxy.receive_playback_status_changed().await;
+    let mut status = ctx.call(proxy.playback_status()).await?;
+    let mut last: Option<Metadata> = None;
+    let mut printed_status = None;
+    let mut locks = match ctx.config.pause_on_lock {
+        true => lock_changes(proxy, ctx).await,
+        false => None,
+    };
+    // Only music that was paused because of the lock is started again
+    let mut paused_by_lock = false;
+    loop {
+        tokio::select! {
+            Some(song) = songs.next() => {
+                let metadata = song?;
+                if let Some(cmd) = &exec {
+                    run_hook(cmd, &metadata);
+                }
+                if notify {
+                    what(ctx, metadata.clone()).await;
+                }
+                last = Some(metadata);
+                // A new song is printed even when the status stays the same
+                printed_status = None;
+            }
+            Some(change) = status_changes.next() => {
+                status = ctx.call(change.get()).await?;
+                // Whoever started it during the lock doesn't need it started again
+                if status == "Playing" {
+                    paused_by_lock = false;
+                }
+            }
+            Some(signal) = next_lock(&mut locks) => {
+                let locked = signal.args().map(|args| args.active);
+                if locked.as_ref() == Ok(&true) && status == "Playing" {
+                    ctx.call(proxy.pause()).await?;
+                    paused_by_lock = true;
+                } else if locked.as_ref() == Ok(&false) && paused_by_lock {
+                    ctx.call(proxy.play()).await?;
+                    paused_by_lock = false;
+                }
+            }
+            else => return Ok(()),
+        }
+
+        if let Some(metadata) = last.as_ref().filter(|_| json) {
+            if printed_status.as_ref() != Some(&status) {
+                print_event(metadata, &status)?;
+                printed_status = Some(status.clone());
+            }
+        }
+    }
+}
+
+/// Prints the song as a line of JSON every time it changes, until the player goes away
+async fn follow_metadata(proxy: &PlayerProxy<'_>, ctx: &Context) -> Result<(), Error> {
+    let mut songs = songs(proxy, ctx).await?;
+    while let Some(song) = songs.next().await {
+        let mut stdout = std::io::stdout().lock();
+        writeln!(stdout, "{}", serde_json::to_string(&song?).unwrap())?;
+        stdout.flush()?;
+    }
+    Ok(())
+}
+
+/// The songs the player plays, starting with the current one, until the player goes away.
+/// Metadata that can't be read (like during some ads) is skipped, and so are repeats of the
+/// last song, Spotify sends the same metadata multiple times per song.
+async fn songs<'a>(
+    proxy: &PlayerProxy<'a>,
+    ctx: &Context,
+) -> Result<impl Stream<Item = Result<Metadata, Error>> + Unpin + 'a, Error> {
+    // Subscribed to before reading the current song, so no change in between is missed
+    let changes = proxy.receive_metadata_changed().await;
+    let current = ctx.metadata(proxy).await?;
+    // The stream outlives `ctx`, so it only takes the timeout along
+    let timeout = ctx.config.timeout(Timeout::Dbus)?;
+    let mut last = None;
+    let songs = futures_util::stream::once(async { Ok(Some(current)) })
+        .chain(changes.then(move |change| async move {
+            let value = match tokio::time::timeout(timeout, change.get()).await {
+                Ok(value) => value?,
+                Err(_) => return Err(Error::TimedOut(Timeout::Dbus, timeout)),
+            };
+            Ok(value.try_into().ok())
+        }))
+        .filter_map(move |song: Result<Option<Metadata>, Error>| {
+            let song = song.transpose().filter(|song| match song {
+                Ok(metadata) => {
+                    let key = song_key(metadata);
+                    let repeated = last.as_ref() == Some(&key);
+                    last = Some(key);
+                    !repeated
+                }
+                Err(_) => true,
+            });
+            std::future::ready(song)
+        });
+    Ok(Box::pin(songs))
+}
+
+/// What tells songs apart, the trackid or everything else for players without trackids
+fn song_key(metadata: &Metadata) -> String {
+    match metadata.trackid.is_empty() {
+        true => serde_json::to_string(metadata).unwrap(),
+        false => metadata.trackid.clone(),
+    }
+}
+
+/// Listens for the screen locking and unlocking, `None` when there's no screen locker to listen to
+async fn lock_changes(
+    proxy: &PlayerProxy<'_>,
+    ctx: &Context,
+) -> Option<ActiveChangedStream<'static>> {
+    let changes = async {
+        let screensaver = ScreenSaverProxy::new(proxy.inner().connection()).await?;
+        screensaver.receive_active_changed().await
+    };
+    ctx.call(changes)
+        .await
+        .map_err(|e| eprintln!("Can't pause when the screen locks: {}", e))
+        .ok()
+}
+
+/// The next lock change, or never when there's nothing to listen to
+async fn next_lock(locks: &mut Option<ActiveChangedStream<'static>>) -> Option<ActiveChanged> {
+    match locks {
+        Some(locks) => locks.next().await,
+        None => std::future::pending().await,
+    }
+}
+
+#[derive(Serialize)]
+struct WatchEvent<'a> {
+    #[serde(flatten)]
+    metadata: &'a Metadata,
+    status: &'a str,
+}
+
+/// Prints the song and status as a single line of JSON
+fn print_event(metadata: &Metadata, status: &str) -> std::io::Result<()> {
+    let event = WatchEvent { metadata, status };
+    let mut stdout = std::io::stdout().lock();
+    writeln!(stdout, "{}", serde_json::to_string(&event).unwrap())?;
+    stdout.flush()
+}
+
+/// Starts `cmd` with the song in its environment, without waiting for it to finish
+fn run_hook(cmd: &str, metadata: &Metadata) {
+    let child = tokio::process::Command::new("sh")
+        .arg("-c")
+        .arg(cmd)
+        .env("SP_TITLE", &metadata.title)
+        .env("SP_ARTIST", metadata.artists.join(", "))
+        .env("SP_ALBUM", &metadata.album)
+        .env("SP_ARTWORK", &metadata.artwork)
+        .spawn();
+    match child {
+        Ok(mut child) => {
+            tokio::spawn(async move { child.wait().await });
+        }
+        Err(e) => eprintln!("Couldn't run {}: {}", cmd, e),
+    }
+}
+
+/// Jumps to a time in the current song
+async fn seek_to(proxy: &PlayerProxy<'_>, to: Duration) -> Result<(), Error> {
+    let metadata = metadata(proxy).await?;
+    // Players ignore positions past the end, so it's better to say so than to do nothing
+    if let Some(length) = metadata.length {
+        let length = Duration::from_micros(length.max(0) as u64);
+        if to > length {
+            return Err(Error::SeekPastEnd { to, length });
+        }
+    }
+    // The track id makes sure the position isn't applied to a song that started in the meantime
+    let trackid = ObjectPath::try_from(metadata.trackid).map_err(|_| Error::NoTrackId)?;
+    let position = i64::try_from(to.as_micros()).map_err(|_| Error::SeekTooFar(to))?;
+    Ok(proxy.set_position(&trackid, position).await?)
+}
+
+/// Sets the playback rate after checking it against the bounds the player supports
+async fn set_rate(proxy: &PlayerProxy<'_>, rate: f64, clamp: bool) -> Result<(), Error> {
+    let min = proxy.minimum_rate().await.ok();
+    let max = proxy.maximum_rate().await.ok();
+    Ok(proxy.set_rate(fit_rate(rate, min, max, clamp)?).await?)
+}
+
+/// Checks the rate against the bounds the player reported, or moves it within them with `clamp`.
+/// Players that don't expose the bounds, or have ones that make no sense, are left to deal with
+/// the rate themselves.
+fn fit_rate(rate: f64, min: Option<f64>, max: Option<f64>, clamp: bool) -> Result<f64, Error> {
+    let min = min.filter(|min| !min.is_nan()).unwrap_or(f64::NEG_INFINITY);
+    let max = max.filter(|max| !max.is_nan()).unwrap_or(f64::INFINITY);
+    if min > max || (min..=max).contains(&rate) {
+        Ok(rate)
+    } else if clamp {
+        Ok(rate.clamp(min, max))
+    } else {
+        Err(Error::RateOutOfRange { rate, min, max })
+    }
+}
+
+/// Reads the volume. MPRIS says it's an f64, but some players use other numeric types.
+async fn volume(proxy: &PlayerProxy<'_>) -> Result<f64, Error> {
+    as_volume(&proxy.volume().await?.into()).ok_or(Error::InvalidVolume)
+}
+
+/// Sets the volume, using the same numeric type the player uses for it
+async fn set_volume(proxy: &PlayerProxy<'_>, volume: f64) -> Result<(), Error> {
+    let current = proxy.volume().await.ok();
+    Ok(proxy
+        .set_volume(volume_value(current.as_deref(), volume))
+        .await?)
+}
+
+/// Changes the volume by `delta` and returns the new volume, kept between 0.0 and 1.0.
+/// Two of these running at once can still lose one of the changes, since MPRIS has no way to
+/// change the volume relatively. The volume is read from the player right before it's written,
+/// so that only happens when they really overlap.
+async fn change_volume(proxy: &PlayerProxy<'_>, delta: f64) -> Result<f64, Error> {
+    let current = state::fresh(proxy, "Volume").await?;
+    let volume = (as_volume(&current).ok_or(Error::InvalidVolume)? + delta).clamp(0.0, 1.0);
+    proxy
+        .set_volume(volume_value(Some(&current), volume))
+        .await?;
+    Ok(volume)
+}
+
+/// Players that report the volume as an integer (which MPRIS doesn't allow) use percentages
+const INTEGER_VOLUME_MAX: f64 = 100.0;
+
+/// The volume as a value of the same numeric type as `current`, or an f64 like MPRIS says.
+/// Integer volumes are written as a percentage, see [`INTEGER_VOLUME_MAX`].
+fn volume_value(current: Option<&Value<'_>>, volume: f64) -> Value<'static> {
+    let percent = (volume * INTEGER_VOLUME_MAX).round();
+    match current {
+        Some(Value::Value(current)) => volume_value(Some(current), volume),
+        Some(Value::U8(_)) => Value::U8(percent as u8),
+        Some(Value::I16(_)) => Value::I16(percent as i16),
+        Some(Value::U16(_)) => Value::U16(percent as u16),
+        Some(Value::I32(_)) => Value::I32(percent as i32),
+        Some(Value::U32(_)) => Value::U32(percent as u32),
+        Some(Value::I64(_)) => Value::I64(percent as i64),
+        Some(Value::U64(_)) => Value::U64(percent as u64),
+        _ => Value::F64(volume),
+    }
+}
+
+/// Reads a volume of any numeric type, where 1.0 is the maximum like for an f64
+fn as_volume(value: &Value<'_>) -> Option<f64> {
+    let percent = match value {
+        Value::F64(n) => return Some(*n),
+        Value::Value(v) => return as_volume(v),
+        Value::U8(n) => *n as f64,
+        Value::I16(n) => *n as f64,
+        Value::U16(n) => *n as f64,
+        Value::I32(n) => *n as f64,
+        Value::U32(n) => *n as f64,
+        Value::I64(n) => *n as f64,
+        Value::U64(n) => *n as f64,
+        _ => return None,
+    };
+    Some(percent / INTEGER_VOLUME_MAX)
+}
+
+async fn what(ctx: &Context, metadata: Metadata) {
+    let options = &ctx.options;
+    let artists = options.artists(&metadata.artists);
+    let body = match (artists.is_empty(), metadata.album.is_empty()) {
+        (_, true) => artists,
+        (true, false) => options.name(&metadata.album),
+        (false, false) => format!("{} - {}", artists, options.name(&metadata.album)),
+    };
+    let summary = options.name(&metadata.title);
+    show_notification(ctx, &summary, &body, &ctx.artwork_url(&metadata)).await
+}
+
+/// How long to wait for the notification daemon to accept a notification
+const NOTIFICATION_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Shows a notification, with the artwork as its image unless it's empty.
+/// The notification doesn't have actions, so this returns as soon as the daemon has accepted it
+/// instead of waiting for the user to do something with it (like `wait_for_action` would).
+/// The artwork is downloaded to a temp file that is removed afterwards, unless `--keep-artwork` is used.
+async fn show_notification(ctx: &Context, summary: &str, body: &str, artwork: &str) {
+    // The temp file has to outlive the notification, so it's kept until the end
+    let (_tmp, image) = artwork_file(ctx, artwork).await;
+    let message = Message {
+        summary: summary.to_string(),
+        body: body.to_string(),
+        image,
+    };
+
+    // show() blocks until the notification daemon replies, which could take forever when it's
+    // stuck. It's run on its own thread so this can give up, a detached thread doesn't keep the
+    // process alive like a tokio blocking task would.
+    let notifier = ctx.notify_backend.notifier();
+    let (tx, rx) = tokio::sync::oneshot::channel();
+    std::thread::spawn(move || tx.send(notifier.show(&message)));
+    match tokio::time::timeout(NOTIFICATION_TIMEOUT, rx).await {
+        Ok(Ok(Ok(()))) => {}
+        Ok(Ok(Err(e))) => eprintln!("Couldn't show the notification: {}", e),
+        _ => eprintln!("The notification daemon didn't respond"),
+    }
+}
+
+/// Downloads the artwork for a notification, into a temp file that's removed when it's dropped
+/// or `artwork` in the cache directory with `--keep-artwork`
+#[cfg(feature = "notifications")]
+async fn artwork_file(
+    ctx: &Context,
+    artwork: &str,
+) -> (Option<temp_file::TempFile>, Option<String>) {
+    let mut tmp = None;
+    let bytes = match artwork {
+        "" => None,
+        // The notification is still useful without its image
+        url => ctx
+            .timed(
+                Timeout::Artwork,
+                http::artwork(&ctx.client, url, ctx.config.artwork_max_bytes),
+            )
+            .await
+            .map_err(|e| eprintln!("Couldn't get the artwork: {}", e))
+            .ok(),
+    };
+    let path = bytes.and_then(|bytes| {
+        if ctx.keep_artwork {
+            // The temp directory is shared with other users, the cache directory is only ours
+            let dir = match dirs::cache_dir() {
+                Some(dir) => dir.join("spotify-control"),
+                None => {
+                    eprintln!("Couldn't save the artwork, there's no cache directory");
+                    return None;
+                }
+            };
+            let path = dir.join("artwork");
+            match std::fs::create_dir_all(&dir).and_then(|()| std::fs::write(&path, &bytes)) {
+                Ok(()) => eprintln!("Saved the artwork to {}", path.display()),
+                Err(e) => {
+                    eprintln!("Couldn't save the artwork: {}", e);
+                    return None;
+                }
+            }
+            Some(path)
+        } else {
+            Some(
+                tmp.insert(temp_file::with_contents(&bytes))
+                    .path()
+                    .to_path_buf(),
+            )
+        }
+    });
+    // The notification only takes a string path, so one with a weird TMPDIR goes without the image
+    match path.as_deref().map(|path| (path, path.to_str())) {
+        None => (tmp, None),
+        Some((_, Some(path))) => (tmp, Some(path.to_string())),
+        Some((path, None)) => {
+            eprintln!(
+                "Not showing the artwork, {} isn't valid UTF-8",
+                path.display()
+            );
+            (tmp, None)
+        }
+    }
+}
+
+/// Without the notifications feature there's nothing to download artwork with
+#[cfg(not(feature = "notifications"))]
+async fn artwork_file(_ctx: &Context, _artwork: &str) -> (Option<()>, Option<String>) {
+    (None, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::ErrorKind;
+
+    use super::*;
+
+    /// Parses the words after `spotify-control` like clap does for the command line
+    fn parse(words: &[&str]) -> Result<Args, clap::Error> {
+        Args::try_parse_from(std::iter::once("spotify-control").chain(words.iter().copied()))
+    }
+
+    fn command(words: &[&str]) -> Commands {
+        match parse(words) {
+            Ok(args) => args.action.expect("a command was given"),
+            Err(e) => panic!("{:?} didn't parse: {}", words, e),
+        }
+    }
+
+    fn error(words: &[&str]) -> ErrorKind {
+        match parse(words) {
+            Ok(args) => panic!("{:?} parsed as {:?}", words, args),
+            Err(e) => e.kind(),
+        }
+    }
+
+    fn control(notify: bool, json: bool) -> ControlArgs {
+        ControlArgs { notify, json }
+    }
+
+    fn play_args() -> PlayArgs {
+        PlayArgs {
+            force: false,
+            write_uri: None,
+            append: false,
+            verify: false,
+            control: control(false, false),
+        }
+    }
+
+    #[test]
+    fn the_command_is_optional() {
+        assert_eq!(parse(&[]).unwrap().action, None);
+    }
+
+    #[test]
+    fn global_flags() {
+        let args = parse(&[
+            "-s",
+            "vlc",
+            "--trim-feat",
+            "--max-artists",
+            "2",
+            "--keep-artwork",
+            "--artwork-max-bytes",
+            "1000",
+            "--retries",
+            "0",
+            "-v",
+            "-q",
+            "--color",
+            "never",
+            "--notify-backend",
+            "stdout",
+            "stop",
+        ])
+        .unwrap();
+        assert_eq!(args.service_name.as_deref(), Some("vlc"));
+        assert!(args.trim_feat && args.keep_artwork && args.verbose && args.quiet);
+        assert_eq!(args.max_artists, Some(2));
+        assert_eq!(args.artwork_max_bytes, Some(1000));
+        assert_eq!(args.retries, Some(0));
+        assert_eq!(args.color, ColorChoice::Never);
+        assert_eq!(args.notify_backend, NotifyBackend::Stdout);
+        assert_eq!(
+            args.action,
+            Some(Commands::Stop {
+                control: control(false, false)
+            })
+        );
+    }
+
+    #[test]
+    fn skipping() {
+        assert_eq!(
+            command(&["next"]),
+            Commands::Next {
+                count: 1,
+                control: control(false, false)
+            }
+        );
+        assert_eq!(
+            command(&["next", "-c", "3", "--notify", "--json"]),
+            Commands::Next {
+                count: 3,
+                control: control(true, true)
+            }
+        );
+        assert_eq!(
+            command(&["previous", "--count", "20", "-n"]),
+            Commands::Previous {
+                count: 20,
+                control: control(true, false)
+            }
+        );
+    }
+
+    #[test]
+    fn skipping_too_little_or_too_much() {
+        assert_eq!(error(&["next", "--count", "0"]), ErrorKind::ValueValidation);
+        assert_eq!(
+            error(&["next", "--count", "21"]),
+            ErrorKind::ValueValidation
+        );
+        assert_eq!(error(&["previous", "-c", "-1"]), ErrorKind::UnknownArgument);
+    }
+
+    #[test]
+    fn playback() {
+        assert_eq!(
+            command(&["play", "-n"]),
+            Commands::Play {
+                #[cfg(feature = "web-api")]
+                device: None,
+                control: control(true, false)
+            }
+        );
+        assert_eq!(
+            command(&["pause", "--json"]),
+            Commands::Pause {
+                control: control(false, true)
+            }
+        );
+        assert_eq!(
+            command(&["stop"]),
+            Commands::Stop {
+                control: control(false, false)
+            }
+        );
+        let play_pause = Commands::PlayPause {
+            control: control(false, false),
+        };
+        assert_eq!(command(&["play-pause"]), play_pause);
+        assert_eq!(command(&["toggle"]), play_pause);
+    }
+
+    #[cfg(feature = "web-api")]
+    #[test]
+    fn web_api() {
+        assert_eq!(
+            command(&["play", "--device", "Living Room"]),
+            Commands::Play {
+                device: Some("Living Room".to_string()),
+                control: control(false, false)
+            }
+        );
+        assert_eq!(
+            error(&["play", "--device", "default", "--notify"]),
+            ErrorKind::ArgumentConflict
+        );
+        assert_eq!(
+            command(&["like"]),
+            Commands::Like {
+                unlike: false,
+                toggle: false
+            }
+        );
+        assert_eq!(
+            command(&["like", "--unlike"]),
+            Commands::Like {
+                unlike: true,
+                toggle: false
+            }
+        );
+        assert_eq!(
+            error(&["like", "--unlike", "--toggle"]),
+            ErrorKind::ArgumentConflict
+        );
+        assert_eq!(
+            command(&["saved", "--icon"]),
+            Commands::Saved { icon: true }
+        );
+        assert_eq!(command(&["auth"]), Commands::Auth);
+    }
+
+    #[test]
+    fn the_application() {
+        assert_eq!(command(&["raise"]), Commands::Raise { force: false });
+        assert_eq!(
+            command(&["quit", "--force"]),
+            Commands::Quit { force: true }
+        );
+    }
+
+    #[test]
+    fn now_playing() {
+        let default = Commands::NowPlaying {
+            artwork_size: None,
+            format: None,
+            duration_style: DurationStyle::Compact,
+        };
+        assert_eq!(command(&["now-playing"]), default);
+        assert_eq!(command(&["np"]), default);
+        assert_eq!(
+            command(&[
+                "np",
+                "-f",
+                "{title} - {artist}",
+                "--duration-style",
+                "padded",
+                "--artwork-size",
+                "64"
+            ]),
+            Commands::NowPlaying {
+                artwork_size: Some(64),
+                format: Some("{title} - {artist}".to_string()),
+                duration_style: DurationStyle::Padded,
+            }
+        );
+        assert_eq!(
+            error(&["np", "--duration-style", "long"]),
+            ErrorKind::InvalidValue
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "tui")]
+    fn info() {
+        assert_eq!(
+            command(&["info"]),
+            Commands::Info {
+                watch: false,
+                interval: Duration::from_secs(1)
+            }
+        );
+        assert_eq!(
+            command(&["info", "-w", "--interval", "0.5"]),
+            Commands::Info {
+                watch: true,
+                interval: Duration::from_millis(500)
+            }
+        );
+        for interval in ["0", "inf", "1e300"] {
+            assert_eq!(
+                error(&["info", "--interval", interval]),
+                ErrorKind::ValueValidation
+            );
+        }
+    }
+
+    #[test]
+    #[cfg(not(feature = "tui"))]
+    fn info_without_the_dashboard() {
+        assert_eq!(command(&["info"]), Commands::Info {});
+        assert_eq!(error(&["info", "--watch"]), ErrorKind::UnknownArgument);
+    }
+
+    #[test]
+    fn the_song() {
+        assert_eq!(
+            command(&["artwork-url"]),
+            Commands::ArtworkUrl { size: None }
+        );
+        assert_eq!(
+            command(&["artwork-url", "--size", "300"]),
+            Commands::ArtworkUrl { size: Some(300) }
+        );
+        assert_eq!(
+            command(&["metadata", "--json"]),
+            Commands::Metadata {
+                json: true,
+                follow: false
+            }
+        );
+        assert_eq!(
+            command(&["metadata", "-f"]),
+            Commands::Metadata {
+                json: false,
+                follow: true
+            }
+        );
+    }
+
+    #[test]
+    fn watching() {
+        assert_eq!(
+            command(&["watch"]),
+            Commands::Watch {
+                no_notify: false,
+                exec: None,
+                json: false
+            }
+        );
+        assert_eq!(
+            command(&["watch", "--no-notify", "-e", "echo $SP_TITLE", "--json"]),
+            Commands::Watch {
+                no_notify: true,
+                exec: Some("echo $SP_TITLE".to_string()),
+                json: true
+            }
+        );
+    }
+
+    #[test]
+    fn playing_a_uri() {
+        assert_eq!(
+            command(&["play-song", "uri", "spotify:track:x"]),
+            Commands::PlaySong {
+                mode: PlayMode::Uri {
+                    uri: "spotify:track:x".to_string()
+                },
+                args: play_args(),
+            }
+        );
+        assert_eq!(
+            command(&[
+                "play-song",
+                "--force",
+                "--write-uri",
+                "uris",
+                "--append",
+                "--verify",
+                "-n",
+                "uri",
+                "spotify:track:x"
+            ]),
+            Commands::PlaySong {
+                mode: PlayMode::Uri {
+                    uri: "spotify:track:x".to_string()
+                },
+                args: PlayArgs {
+                    force: true,
+                    write_uri: Some(PathBuf::from("uris")),
+                    append: true,
+                    verify: true,
+                    control: control(true, false),
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn appending_needs_a_file() {
+        assert_eq!(
+            error(&["play-song", "--append", "uri", "spotify:track:x"]),
+            ErrorKind::MissingRequiredArgument
+        );
+    }
+
+    #[cfg(feature = "search")]
+    #[test]
+    fn searching() {
+        assert_eq!(
+            command(&["play-song", "search", "get lucky", "daft", "punk"]),
+            Commands::PlaySong {
+                mode: PlayMode::Search {
+                    query: vec!["get lucky".into(), "daft".into(), "punk".into()],
+                    list: false,
+                    select: None,
+                    resolve_only: false,
+                    page: Page {
+                        count: None,
+                        offset: 0
+                    },
+                    sort: SortOrder::Relevance,
+                },
+                args: play_args(),
+            }
+        );
+        assert_eq!(
+            command(&[
+                "play-song",
+                "search",
+                "-l",
+                "-c",
+                "10",
+                "--offset",
+                "5",
+                "--sort",
+                "duration",
+                "--resolve-only",
+                "--json",
+                "megalovania"
+            ]),
+            Commands::PlaySong {
+                mode: PlayMode::Search {
+                    query: vec!["megalovania".into()],
+                    list: true,
+                    select: None,
+                    resolve_only: true,
+                    page: Page {
+                        count: Some(10),
+                        offset: 5
+                    },
+                    sort: SortOrder::Duration,
+                },
+                args: PlayArgs {
+                    control: control(false, true),
+                    ..play_args()
+                },
+            }
+        );
+        assert_eq!(
+            command(&["play-song", "last", "--select", "2", "--offset", "1"]),
+            Commands::PlaySong {
+                mode: PlayMode::Last {
+                    select: Some(2),
+                    page: Page {
+                        count: None,
+                        offset: 1
+                    },
+                    resolve_only: false,
+                },
+                args: play_args(),
+            }
+        );
+        // There's one --json and one --notify, wherever they're given
+        assert_eq!(
+            command(&["play-song", "--json", "search", "-n", "x"]),
+            command(&["play-song", "search", "x", "--notify", "--json"]),
+        );
+    }
+
+    #[cfg(feature = "search")]
+    #[test]
+    fn invalid_searches() {
+        assert_eq!(
+            error(&["play-song", "search", "--select", "1", "--list", "x"]),
+            ErrorKind::ArgumentConflict
+        );
+        assert_eq!(
+            error(&["play-song", "search", "-c", "0", "x"]),
+            ErrorKind::ValueValidation
+        );
+        assert_eq!(
+            error(&["play-song", "search", "-c", "51", "x"]),
+            ErrorKind::ValueValidation
+        );
+        assert_eq!(
+            error(&["play-song", "search", "--offset", "50", "x"]),
+            ErrorKind::ValueValidation
+        );
+        assert_eq!(
+            error(&["play-song", "search", "--sort", "name", "x"]),
+            ErrorKind::InvalidValue
+        );
+    }
+
+    #[test]
+    fn seeking() {
+        assert_eq!(
+            command(&["seek", "--to", "1:30"]),
+            Commands::Seek {
+                to: Some(Duration::from_secs(90)),
+                by: None
+            }
+        );
+        assert_eq!(
+            command(&["seek", "--by", "-1:00"]),
+            Commands::Seek {
+                to: None,
+                by: Some(-60_000_000)
+            }
+        );
+        assert_eq!(
+            command(&["seek", "--by", "+10"]),
+            Commands::Seek {
+                to: None,
+                by: Some(10_000_000)
+            }
+        );
+        assert_eq!(error(&["seek"]), ErrorKind::MissingRequiredArgument);
+        assert_eq!(
+            error(&["seek", "--to", "1", "--by", "1"]),
+            ErrorKind::ArgumentConflict
+        );
+        assert_eq!(error(&["seek", "--to", "1:75"]), ErrorKind::ValueValidation);
+    }
+
+    #[test]
+    fn times() {
+        assert_eq!(parse_time("0"), Ok(Duration::ZERO));
+        assert_eq!(parse_time("1.5"), Ok(Duration::from_millis(1500)));
+        assert_eq!(parse_time("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_time("1:30"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_time("1:02:03"), Ok(Duration::from_secs(3723)));
+        assert_eq!(parse_time("75:00"), Ok(Duration::from_secs(4500)));
+        for invalid in ["", "-1", "1:60", "1:75:00", "1:2:3:4", "a:00", "inf", "NaN"] {
+            assert!(parse_time(invalid).is_err(), "{:?} parsed", invalid);
+        }
+    }
+
+    #[test]
+    fn huge_times_are_rejected() {
+        assert!(parse_time("1e300").is_err());
+        assert!(parse_time("400000000000000000:00").is_err());
+        assert!(parse_time("18446744073709551615:00:00").is_err());
+        assert!(parse_offset("-1e300").is_err());
+        // Fits in a Duration, but not in the microseconds MPRIS takes
+        assert!(parse_offset("+10000000000000000").is_err());
+        assert_eq!(parse_offset("-1:00"), Ok(-60_000_000));
+        assert_eq!(
+            error(&["seek", "--to", "1e300"]),
+            ErrorKind::ValueValidation
+        );
+        assert_eq!(
+            error(&["seek", "--by", "-1e300"]),
+            ErrorKind::ValueValidation
+        );
+    }
+
+    #[test]
+    fn settings() {
+        assert_eq!(
+            command(&["set", "rate", "1.5", "--clamp"]),
+            Commands::Set {
+                property: SetProperty::Rate {
+                    rate: 1.5,
+                    clamp: true
+                }
+            }
+        );
+        assert_eq!(
+            command(&["set", "volume", "0.5"]),
+            Commands::Set {
+                property: SetProperty::Volume {
+                    volume: Some(0.5),
+                    relative: None
+                }
+            }
+        );
+        assert_eq!(
+            command(&["set", "volume", "--relative", "-0.1"]),
+            Commands::Set {
+                property: SetProperty::Volume {
+                    volume: None,
+                    relative: Some(-0.1)
+                }
+            }
+        );
+    }
+
+    #[test]
+    fn volumes_of_every_numeric_type() {
+        assert_eq!(as_volume(&Value::F64(0.5)), Some(0.5));
+        assert_eq!(as_volume(&Value::U8(100)), Some(1.0));
+        assert_eq!(as_volume(&Value::I16(25)), Some(0.25));
+        assert_eq!(as_volume(&Value::U16(50)), Some(0.5));
+        assert_eq!(as_volume(&Value::I32(0)), Some(0.0));
+        assert_eq!(as_volume(&Value::U32(75)), Some(0.75));
+        assert_eq!(as_volume(&Value::I64(100)), Some(1.0));
+        assert_eq!(as_volume(&Value::U64(10)), Some(0.1));
+        assert_eq!(
+            as_volume(&Value::Value(Box::new(Value::U16(50)))),
+            Some(0.5)
+        );
+        assert_eq!(as_volume(&Value::from("loud")), None);
+    }
+
+    #[test]
+    fn volumes_are_written_as_the_type_that_was_read() {
+        assert_eq!(volume_value(None, 0.5), Value::F64(0.5));
+        assert_eq!(volume_value(Some(&Value::F64(1.0)), 0.5), Value::F64(0.5));
+        assert_eq!(volume_value(Some(&Value::U8(1)), 0.5), Value::U8(50));
+        assert_eq!(volume_value(Some(&Value::I16(1)), 0.255), Value::I16(26));
+        assert_eq!(volume_value(Some(&Value::U16(1)), 0.5), Value::U16(50));
+        assert_eq!(volume_value(Some(&Value::I32(1)), 1.0), Value::I32(100));
+        assert_eq!(volume_value(Some(&Value::U32(1)), 0.0), Value::U32(0));
+        assert_eq!(volume_value(Some(&Value::I64(1)), 0.1), Value::I64(10));
+        assert_eq!(volume_value(Some(&Value::U64(1)), 0.7), Value::U64(70));
+        let wrapped = Value::Value(Box::new(Value::U16(1)));
+        assert_eq!(volume_value(Some(&wrapped), 0.5), Value::U16(50));
+        // Something that isn't a number is replaced with what MPRIS asks for
+        assert_eq!(
+            volume_value(Some(&Value::from("loud")), 0.5),
+            Value::F64(0.5)
+        );
+    }
+
+    #[test]
+    fn integer_volumes_keep_their_scale() {
+        for (current, volume) in [(Value::U16(50), 0.5), (Value::U8(100), 1.0)] {
+            let read = as_volume(&current).unwrap();
+            assert_eq!(read, volume);
+            assert_eq!(as_volume(&volume_value(Some(&current), read)), Some(volume));
+        }
+        let raised = (as_volume(&Value::U16(50)).unwrap() + 0.1).clamp(0.0, 1.0);
+        assert_eq!(volume_value(Some(&Value::U16(50)), raised), Value::U16(60));
+    }
+
+    #[test]
+    fn invalid_volumes() {
+        assert_eq!(error(&["set", "volume", "1.5"]), ErrorKind::ValueValidation);
+        assert_eq!(
+            error(&["set", "volume", "-0.5"]),
+            ErrorKind::UnknownArgument
+        );
+        assert_eq!(
+            error(&["set", "volume", "loud"]),
+            ErrorKind::ValueValidation
+        );
+        assert_eq!(
+            error(&["set", "volume", "--relative", "2"]),
+            ErrorKind::ValueValidation
+        );
+        assert_eq!(
+            error(&["set", "volume"]),
+            ErrorKind::MissingRequiredArgument
+        );
+        assert_eq!(
+            error(&["set", "volume", "0.5", "--relative", "0.1"]),
+            ErrorKind::ArgumentConflict
+        );
+    }
+
+    #[test]
+    fn invalid_rates() {
+        for rate in ["0", "0.0", "NaN", "inf", "fast"] {
+            assert_eq!(
+                error(&["set", "rate", rate]),
+                ErrorKind::ValueValidation,
+                "{}",
+                rate
+            );
+        }
+        // Taken for a flag, like negative volumes
+        assert_eq!(error(&["set", "rate", "-1"]), ErrorKind::UnknownArgument);
+    }
+
+    #[test]
+    fn fitting_rates() {
+        let fit = |rate, min, max, clamp| fit_rate(rate, min, max, clamp).ok();
+        assert_eq!(fit(1.5, Some(0.5), Some(2.0), false), Some(1.5));
+        assert_eq!(fit(3.0, Some(0.5), Some(2.0), false), None);
+        assert_eq!(fit(3.0, Some(0.5), Some(2.0), true), Some(2.0));
+        assert_eq!(fit(0.1, Some(0.5), Some(2.0), true), Some(0.5));
+        // Missing bounds, and ones that make no sense, leave it to the player
+        assert_eq!(fit(3.0, Some(0.5), None, false), Some(3.0));
+        assert_eq!(fit(3.0, None, None, true), Some(3.0));
+        assert_eq!(fit(3.0, Some(2.0), Some(0.5), true), Some(3.0));
+        assert_eq!(fit(3.0, Some(f64::NAN), Some(2.0), true), Some(2.0));
+        assert_eq!(fit(0.1, Some(0.5), Some(f64::NAN), true), Some(0.5));
+    }
+
+    #[cfg(feature = "http-server")]
+    #[test]
+    fn http_server() {
+        assert_eq!(
+            command(&["http", "-p", "9000", "--bind", "0.0.0.0"]),
+            Commands::Http {
+                port: 9000,
+                bind: std::net::Ipv4Addr::UNSPECIFIED.into()
+            }
+        );
+    }
+
+    #[test]
+    fn the_rest() {
+        assert_eq!(
+            command(&["version", "-v"]),
+            Commands::Version { verbose: true }
+        );
+        assert_eq!(command(&["dump-bus-names"]), Commands::DumpBusNames);
+        assert_eq!(
+            command(&["config", "--json"]),
+            Commands::Config {
+                action: None,
+                json: true
+            }
+        );
+        assert_eq!(
+            command(&["config", "init", "-f"]),
+            Commands::Config {
+                action: Some(ConfigAction::Init { force: true }),
+                json: false
+            }
+        );
+        // The command is optional, so an unknown one is just an unexpected argument
+        assert_eq!(error(&["shuffle"]), ErrorKind::UnknownArgument);
+        assert_eq!(
+            error(&["--max-artists", "0", "np"]),
+            ErrorKind::ValueValidation
+        );
+    }
+
+    #[cfg(any(feature = "search", feature = "web-api"))]
+    #[test]
+    fn answers() {
+        assert_eq!(prompt("? ", &b" 3 \n"[..]).unwrap(), "3");
+        // The last line doesn't have to end with a newline
+        assert_eq!(prompt("? ", &b"2"[..]).unwrap(), "2");
+        assert_eq!(prompt("? ", &b"\n"[..]).unwrap(), "");
+    }
+
+    #[cfg(any(feature = "search", feature = "web-api"))]
+    #[test]
+    fn no_answer_cancels() {
+        assert!(matches!(prompt("? ", &b""[..]), Err(Error::Cancelled)));
+    }
+
+    #[cfg(feature = "search")]
+    fn tracks(count: usize) -> Vec<Track> {
+        (0..count)
+            .map(|i| {
+                serde_json::from_value(serde_json::json!({ "id": i.to_string(), "name": "Song" }))
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    #[cfg(feature = "search")]
+    #[test]
+    fn fewer_results_than_asked_for() {
+        let tracks = tracks(2);
+        let page = Page {
+            count: Some(10),
+            offset: 0,
+        };
+        let shown = page.shown(&tracks, 5);
+        assert_eq!(shown.len(), 2);
+        let options = DisplayOptions::default();
+        assert_eq!(pick(shown, &options, &b"1\n"[..]).unwrap().id, "1");
+        assert!(matches!(
+            pick(shown, &options, &b"2\n"[..]),
+            Err(Error::InvalidSelection(input)) if input == "2"
+        ));
+    }
+
+    #[cfg(feature = "search")]
+    #[test]
+    fn nothing_to_pick_from() {
+        assert!(matches!(
+            pick(&[], &DisplayOptions::default(), &b"0\n"[..]),
+            Err(Error::NoSuchResult { found: 0, .. })
+        ));
+    }
+
+    fn words(line: &str) -> Vec<String> {
+        split_words(line).unwrap()
+    }
+
+    #[test]
+    fn splitting_words() {
+        assert_eq!(words("  info   --watch "), ["info", "--watch"]);
+        assert_eq!(words(""), Vec::<String>::new());
+        assert_eq!(
+            words(r#"np --format "{title} - {artist}""#),
+            ["np", "--format", "{title} - {artist}"]
+        );
+        assert_eq!(
+            words("np -f '{title} \"x\"'"),
+            ["np", "-f", "{title} \"x\""]
+        );
+        assert_eq!(words(r#"a "b \"c\" \\" d"#), ["a", r#"b "c" \"#, "d"]);
+        assert_eq!(words(r"a\ b c"), ["a b", "c"]);
+        assert_eq!(words(r#"a"b"'c' """#), ["abc", ""]);
+        assert!(split_words("np -f '{title}").is_err());
+        assert!(split_words(r#"np -f "{title}"#).is_err());
+        assert!(split_words(r"np \").is_err());
+    }
+
+    fn default(command: &str) -> Result<Commands, String> {
+        let config = Config {
+            default_command: Some(command.to_string()),
+            ..Config::default()
+        };
+        default_command(&config).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn default_commands() {
+        assert_eq!(
+            default("play-pause"),
+            Ok(Commands::PlayPause {
+                control: control(false, false)
+            })
+        );
+        assert_eq!(
+            default(r#"now-playing --format "{title} - {artist}""#),
+            Ok(Commands::NowPlaying {
+                artwork_size: None,
+                format: Some("{title} - {artist}".to_string()),
+                duration_style: DurationStyle::Compact,
+            })
+        );
+        #[cfg(feature = "tui")]
+        assert_eq!(
+            default("info --watch --interval 2"),
+            Ok(Commands::Info {
+                watch: true,
+                interval: Duration::from_secs(2)
+            })
+        );
+    }
+
+    #[test]
+    fn without_a_default_command() {
+        for command in ["", "  "] {
+            let config = Config {
+                default_command: Some(command.to_string()),
+                ..Config::default()
+            };
+            let e = default_command(&config).unwrap_err();
+            assert!(matches!(e, Error::NoCommand));
+            assert_eq!(e.exit_code(), 2);
+        }
+        assert!(matches!(
+            default_command(&Config::default()),
+            Err(Error::NoCommand)
+        ));
+    }
+
+    #[test]
+    fn invalid_default_commands() {
+        for (command, error) in [
+            ("--quiet --service-name vlc play-pause", "--quiet"),
+            ("--trim-feat np", "--trim-feat"),
+            ("np --trim-feat", "--trim-feat"),
+            ("shuffle", "shuffle"),
+            ("next --count 0", "has to be between 1 and 20"),
+            ("np -f '{title}", "isn't closed"),
+        ] {
+            match default(command) {
+                Ok(parsed) => panic!("{:?} parsed as {:?}", command, parsed),
+                Err(e) => assert!(
+                    e.starts_with("Invalid default_command in the config file: ")
+                        && e.contains(error),
+                    "{:?} failed with {:?}",
+                    command,
+                    e
+                ),
+            }
+        }
+    }
+
+    /// What a session with Spotify, two VLC windows and a Chromium tab has on the bus
+    const NAMES: &[&str] = &[
+        "org.freedesktop.DBus",
+        ":1.42",
+        "org.freedesktop.Notifications",
+        "org.mpris.MediaPlayer2.vlc.instance1234",
+        "org.mpris.MediaPlayer2.vlc.instance5678",
+        "org.mpris.MediaPlayer2.chromium.instance99",
+        "org.mpris.MediaPlayer2.spotify",
+        "org.kde.StatusNotifierWatcher",
+    ];
+
+    fn resolved(names: &[&str], requested: &str) -> String {
+        resolve_name(names, requested).unwrap()
+    }
+
+    #[test]
+    fn exact_names() {
+        assert_eq!(
+            resolved(NAMES, "org.mpris.MediaPlayer2.spotify"),
+            "org.mpris.MediaPlayer2.spotify"
+        );
+        assert_eq!(
+            resolved(NAMES, "org.mpris.MediaPlayer2.vlc.instance5678"),
+            "org.mpris.MediaPlayer2.vlc.instance5678"
+        );
+        // Players don't have to use the MPRIS prefix to be found by their full name
+        assert_eq!(
+            resolved(NAMES, "org.kde.StatusNotifierWatcher"),
+            "org.kde.StatusNotifierWatcher"
+        );
+    }
+
+    #[test]
+    fn short_names() {
+        assert_eq!(resolved(NAMES, "spotify"), "org.mpris.MediaPlayer2.spotify");
+        assert_eq!(
+            resolved(NAMES, "vlc"),
+            "org.mpris.MediaPlayer2.vlc.instance1234"
+        );
+        assert_eq!(
+            resolved(NAMES, "chromium.instance99"),
+            "org.mpris.MediaPlayer2.chromium.instance99"
+        );
+    }
+
+    #[test]
+    fn instances() {
+        assert_eq!(
+            resolved(NAMES, "org.mpris.MediaPlayer2.vlc"),
+            "org.mpris.MediaPlayer2.vlc.instance1234"
+        );
+        // The instance has to be a part of its own, vl isn't vlc
+        assert_eq!(resolved(NAMES, "vl"), "org.mpris.MediaPlayer2.vl");
+    }
+
+    #[test]
+    fn patterns() {
+        assert_eq!(
+            resolved(NAMES, "chromium.*"),
+            "org.mpris.MediaPlayer2.chromium.instance99"
+        );
+        assert_eq!(
+            resolved(NAMES, "org.mpris.MediaPlayer2.*5678"),
+            "org.mpris.MediaPlayer2.vlc.instance5678"
+        );
+        assert_eq!(
+            resolved(NAMES, "*Notifications"),
+            "org.freedesktop.Notifications"
+        );
+        assert!(matches!(
+            resolve_name(NAMES, "firefox*"),
+            Err(Error::NoMatchingService(pattern)) if pattern == "firefox*"
+        ));
+    }
+
+    #[test]
+    fn matching_patterns() {
+        assert!(matches_pattern("a*", "a"));
+        assert!(matches_pattern("*", ""));
+        assert!(matches_pattern("a*a", "aa"));
+        assert!(matches_pattern("a*a", "aba"));
+        // The start and the end can't share the one a
+        assert!(!matches_pattern("a*a", "a"));
+        assert!(matches_pattern("a*b*c", "aXbYc"));
+        assert!(!matches_pattern("a*b*c", "aXcYb"));
+        assert!(matches_pattern("a*b*b", "abb"));
+        assert!(!matches_pattern("a*b*b", "ab"));
+        assert!(matches_pattern("abc", "abc"));
+        assert!(!matches_pattern("abc", "abcd"));
+    }
+
+    #[test]
+    fn missing_players_keep_their_name() {
+        let names = &["org.freedesktop.DBus"][..];
+        assert_eq!(resolved(names, "vlc"), "org.mpris.MediaPlayer2.vlc");
+        assert_eq!(
+            resolved(names, "org.mpris.MediaPlayer2.vlc"),
+            "org.mpris.MediaPlayer2.vlc"
+        );
+        assert_eq!(resolved(names, "com.example.Player"), "com.example.Player");
+    }
+
+    #[test]
+    fn spotify_is_detected_first() {
+        assert_eq!(detect_service(NAMES), "org.mpris.MediaPlayer2.spotify");
+        assert_eq!(resolved(NAMES, ""), "org.mpris.MediaPlayer2.spotify");
+    }
+
+    #[test]
+    fn detecting_without_spotify() {
+        let names = &[
+            "org.freedesktop.DBus",
+            "org.mpris.MediaPlayer2.vlc.instance1234",
+            "org.mpris.MediaPlayer2.chromium.instance99",
+        ][..];
+        assert_eq!(
+            detect_service(names),
+            "org.mpris.MediaPlayer2.vlc.instance1234"
+        );
+        // Spotify is still asked for when no player is running, so the error is about it
+        assert_eq!(
+            detect_service(&["org.freedesktop.DBus", "org.mpris.MediaPlayer2"]),
+            DEFAULT_SERVICE
+        );
+        assert_eq!(detect_service(&[]), DEFAULT_SERVICE);
+    }
+
+    /// Metadata as a player would send it, `xesam:artist` is a list with the one artist given
+    fn metadata(entries: &[(&str, &str)]) -> Result<Metadata, MetadataError> {
+        let mut map = HashMap::new();
+        for (key, value) in entries {
+            let value = match *key {
+                "xesam:artist" => Value::new(vec![*value]),
+                _ => Value::new(*value),
+            };
+            map.insert(key.to_string(), value);
+        }
+        Metadata::try_from(OwnedValue::from(Value::Dict(map.into())))
+    }
+
+    #[test]
+    fn albums() {
+        let song = metadata(&[
+            ("xesam:title", "Song"),
+            ("xesam:artist", "Artist"),
+            ("xesam:album", "Album"),
+        ])
         .unwrap();
+        assert_eq!(song.album, "Album");
+        assert_eq!(song.artists, ["Artist"]);
+        let single = metadata(&[("xesam:title", "Song")]).unwrap();
+        assert_eq!(single.album, "");
+        assert_eq!(single.title, "Song");
+    }
+
+    #[test]
+    fn titles() {
+        let url = "https://example.com/stream";
+        let titled = metadata(&[("xesam:title", "Song"), ("xesam:url", url)]).unwrap();
+        assert_eq!(titled.title, "Song");
+        let stream = metadata(&[("xesam:url", url)]).unwrap();
+        assert_eq!(stream.title, url);
+        assert_eq!(stream.url, url);
+        assert_eq!(metadata(&[]).unwrap().title, UNKNOWN_TITLE);
+    }
+
+    #[test]
+    fn songs_are_told_apart_by_trackid() {
+        let song = metadata(&[("xesam:title", "Song"), ("mpris:trackid", "/track/1")]).unwrap();
+        let renamed = metadata(&[("xesam:title", "Other"), ("mpris:trackid", "/track/1")]).unwrap();
+        assert_eq!(song_key(&song), song_key(&renamed));
+        let stream = metadata(&[("xesam:title", "Song")]).unwrap();
+        let next = metadata(&[("xesam:title", "Other")]).unwrap();
+        assert_ne!(song_key(&stream), song_key(&next));
+        assert_eq!(song_key(&stream), song_key(&stream.clone()));
+    }
+
+    #[test]
+    fn ads_without_artists() {
+        let ad = metadata(&[("xesam:title", "Advertisement")]).unwrap();
+        assert_eq!(ad.title, "Advertisement");
+        assert!(ad.artists.is_empty());
+        // Nothing at all still reads, as an unknown song
+        assert_eq!(metadata(&[]).unwrap().artists, Vec::<String>::new());
+        assert!(matches!(
+            metadata(&[("xesam:artist", "Artist"), ("xesam:title", "Song")]),
+            Ok(song) if song.artists == ["Artist"]
+        ));
+    }
+
+    #[test]
+    fn songs_are_matched_to_uris() {
+        let spotify = metadata(&[
+            ("mpris:trackid", "/com/spotify/track/4uLU6hMCjMI75M1A2tKUQC"),
+            (
+                "xesam:url",
+                "https://open.spotify.com/track/4uLU6hMCjMI75M1A2tKUQC",
+            ),
+        ])
+        .unwrap();
+        let by_url = metadata(&[(
+            "xesam:url",
+            "https://open.spotify.com/track/4uLU6hMCjMI75M1A2tKUQC",
+        )])
+        .unwrap();
+        let by_trackid =
+            metadata(&[("mpris:trackid", "/com/spotify/track/4uLU6hMCjMI75M1A2tKUQC")]).unwrap();
+        let other = metadata(&[("xesam:url", "file:///music/song.mp3")]).unwrap();
+        // (song, uri) => whether it's the song
+        let cases = [
+            (&spotify, "spotify:track:4uLU6hMCjMI75M1A2tKUQC", true),
+            (&by_url, "spotify:track:4uLU6hMCjMI75M1A2tKUQC", true),
+            (&by_trackid, "spotify:track:4uLU6hMCjMI75M1A2tKUQC", true),
+            (&spotify, "spotify:track:0000000000000000000000", false),
+            (&spotify, "spotify:track:", false),
+            (&spotify, "spotify:album:4uLU6hMCjMI75M1A2tKUQC", false),
+            (&other, "file:///music/song.mp3", true),
+            (&other, "file:///music/other.mp3", false),
+            (&other, "spotify:track:4uLU6hMCjMI75M1A2tKUQC", false),
+        ];
+        for (song, uri, is) in cases {
+            assert_eq!(song.is(uri), is, "{:?} is {}", song, uri);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "web-api")]
+    fn spotify_ids() {
+        let id = "4uLU6hMCjMI75M1A2tKUQC";
+        let cases = [
+            (
+                &[("mpris:trackid", "/com/spotify/track/4uLU6hMCjMI75M1A2tKUQC")][..],
+                Some(id),
+            ),
+            (
+                &[(
+                    "xesam:url",
+                    "https://open.spotify.com/track/4uLU6hMCjMI75M1A2tKUQC",
+                )],
+                Some(id),
+            ),
+            // Ads and podcasts aren't tracks
+            (
+                &[("mpris:trackid", "/com/spotify/ad/4uLU6hMCjMI75M1A2tKUQC")],
+                None,
+            ),
+            (
+                &[(
+                    "xesam:url",
+                    "https://open.spotify.com/episode/4uLU6hMCjMI75M1A2tKUQC",
+                )],
+                None,
+            ),
+            (&[("mpris:trackid", "/com/spotify/track/")], None),
+            (&[("xesam:url", "file:///music/song.mp3")], None),
+            (&[], None),
+        ];
+        for (entries, spotify_id) in cases {
+            let song = metadata(entries).unwrap();
+            assert_eq!(song.spotify_id(), spotify_id, "{:?}", entries);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "search")]
+    fn flattened_args_keep_the_help_of_the_command() {
+        let mut command = Args::command();
+        let play_song = command.find_subcommand_mut("play-song").unwrap();
+        for name in ["search", "last"] {
+            let about = play_song.find_subcommand(name).unwrap().get_about();
+            assert!(!about.unwrap().starts_with("Which part"), "{}", name);
+        }
+    }
+
+    #[test]
+    fn the_clap_definition_is_consistent() {
+        Args::command().debug_assert();
+    }
 }
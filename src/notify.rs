@@ -0,0 +1,107 @@
+use std::io::Write;
+
+use clap::ValueEnum;
+#[cfg(feature = "notifications")]
+use notify_rust::{Hint, Notification};
+
+use crate::Error;
+
+/// How notifications are shown, with `--notify-backend`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum NotifyBackend {
+    /// Talk to the notification daemon directly
+    #[cfg(feature = "notifications")]
+    NotifyRust,
+    /// Print the notifications instead of showing them
+    Stdout,
+    /// Run `notify-send`, for desktops where talking to the daemon directly misbehaves
+    NotifySend,
+}
+
+impl NotifyBackend {
+    pub fn notifier(self) -> Box<dyn Notifier + Send> {
+        match self {
+            #[cfg(feature = "notifications")]
+            NotifyBackend::NotifyRust => Box::new(NotifyRust),
+            NotifyBackend::Stdout => Box::new(Stdout),
+            NotifyBackend::NotifySend => Box::new(NotifySend),
+        }
+    }
+}
+
+/// The notification to show, `image` is the path of a local file
+#[derive(Debug, Clone)]
+pub struct Message {
+    pub summary: String,
+    pub body: String,
+    pub image: Option<String>,
+}
+
+/// Shows notifications. This blocks until the notification was accepted, the caller decides how
+/// long that may take.
+pub trait Notifier {
+    fn show(&self, message: &Message) -> Result<(), Error>;
+}
+
+const APP_NAME: &str = "Spotify Notify";
+
+#[cfg(feature = "notifications")]
+struct NotifyRust;
+
+#[cfg(feature = "notifications")]
+impl Notifier for NotifyRust {
+    fn show(&self, message: &Message) -> Result<(), Error> {
+        let mut notification = Notification::new();
+        notification
+            .appname(APP_NAME)
+            .summary(&message.summary)
+            .body(&message.body)
+            .hint(Hint::Category("music".to_string()));
+        if let Some(image) = &message.image {
+            notification.image_path(image);
+        }
+        notification.show()?;
+        Ok(())
+    }
+}
+
+struct Stdout;
+
+impl Notifier for Stdout {
+    fn show(&self, message: &Message) -> Result<(), Error> {
+        let mut stdout = std::io::stdout().lock();
+        writeln!(stdout, "{}: {}", message.summary, message.body)?;
+        stdout.flush()?;
+        Ok(())
+    }
+}
+
+struct NotifySend;
+
+impl Notifier for NotifySend {
+    fn show(&self, message: &Message) -> Result<(), Error> {
+        let mut command = std::process::Command::new("notify-send");
+        command
+            .arg("--app-name")
+            .arg(APP_NAME)
+            .arg("--category")
+            .arg("music");
+        if let Some(image) = &message.image {
+            command.arg("--icon").arg(image);
+        }
+        // Without the -- a summary starting with a dash would be taken for an option
+        let status = command
+            .arg("--")
+            .arg(&message.summary)
+            .arg(&message.body)
+            .status()
+            .map_err(|e| match e.kind() {
+                std::io::ErrorKind::NotFound => Error::NoNotifySend,
+                _ => e.into(),
+            })?;
+        match status.success() {
+            true => Ok(()),
+            false => Err(Error::NotifySendFailed(status)),
+        }
+    }
+}
@@ -0,0 +1,407 @@
+use std::{cmp::Reverse, fmt::Display, time::Duration};
+
+use clap::ValueEnum;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "web-api")]
+use crate::web_api;
+use crate::{config::Config, display::DisplayOptions, http, Error};
+
+/// Where searches are sent to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchBackend {
+    /// A small public api that doesn't need credentials
+    Heroku,
+    /// The Spotify Web API, which needs `client_id` and `client_secret` in the config file and
+    /// the web-api feature
+    Spotify,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Response {
+    pub tracks: Tracks,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Tracks {
+    #[serde(default)]
+    pub items: Vec<Track>,
+}
+
+/// Only `id` is required, so slightly different responses still parse
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Track {
+    #[serde(default)]
+    pub name: String,
+    pub id: String,
+    #[serde(default)]
+    pub artists: Vec<Artist>,
+    #[serde(default)]
+    pub album: Album,
+    /// Not every search backend provides this
+    #[serde(default)]
+    pub duration_ms: Option<u64>,
+    /// Between 0 and 100, only the Web API provides this
+    #[serde(default)]
+    pub popularity: Option<u32>,
+}
+
+impl Track {
+    pub fn duration(&self) -> Option<Duration> {
+        self.duration_ms.map(Duration::from_millis)
+    }
+
+    pub fn uri(&self) -> String {
+        format!("spotify:track:{}", self.id)
+    }
+}
+
+/// The order search results are listed and picked in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SortOrder {
+    /// The order the backend returned them in
+    Relevance,
+    /// The most popular first, the same as relevance when the backend doesn't know
+    Popularity,
+    /// The shortest first
+    Duration,
+}
+
+/// Sorts the tracks, songs the backend didn't provide the value for go last in their original order
+pub fn sort(tracks: &mut [Track], order: SortOrder) {
+    match order {
+        SortOrder::Relevance => {}
+        // None sorts before Some, so missing values are put last explicitly
+        SortOrder::Popularity => {
+            tracks.sort_by_key(|t| (t.popularity.is_none(), t.popularity.map(Reverse)))
+        }
+        SortOrder::Duration => tracks.sort_by_key(|t| (t.duration_ms.is_none(), t.duration_ms)),
+    }
+}
+
+/// A track with the uri that would be played, printed by `--resolve-only`
+#[derive(Serialize)]
+pub struct ResolvedTrack<'a> {
+    #[serde(flatten)]
+    pub track: &'a Track,
+    pub uri: String,
+}
+
+impl Track {
+    /// Shows the track like its `Display` implementation, but following `options`
+    pub fn display<'a>(&'a self, options: &'a DisplayOptions) -> TrackDisplay<'a> {
+        TrackDisplay {
+            track: self,
+            options,
+        }
+    }
+}
+
+pub struct TrackDisplay<'a> {
+    track: &'a Track,
+    options: &'a DisplayOptions,
+}
+
+impl Display for TrackDisplay<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (shown, hidden) = self.options.cap_artists(&self.track.artists);
+        let artists = shown
+            .iter()
+            .map(|a| self.options.name(&a.name))
+            .collect::<Vec<_>>();
+        write!(f, "{}", self.options.name(&self.track.name))?;
+        // "by A", "by A and B", "by A, B and C", or "by A, B +2 more" when some are hidden
+        match artists.as_slice() {
+            [] => {}
+            artists if hidden > 0 => write!(f, " by {} +{} more", artists.join(", "), hidden)?,
+            [artist] => write!(f, " by {}", artist)?,
+            [start @ .., last] => write!(f, " by {} and {}", start.join(", "), last)?,
+        }
+        if !self.track.album.name.is_empty() {
+            write!(f, " on {}", self.options.name(&self.track.album.name))?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Track {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.display(&DisplayOptions::default()).fmt(f)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Artist {
+    #[serde(default)]
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Album {
+    #[serde(default)]
+    pub name: String,
+}
+
+/// Joins the words of a search into a query, without redundant whitespace. A single argument is
+/// the whole query. When there are more, the arguments with a space in them were quoted on
+/// purpose, so they're kept together as a phrase: `"get lucky" daft punk`.
+pub fn join_query(words: &[String]) -> String {
+    let words = words
+        .iter()
+        .map(|word| word.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>();
+    match words.as_slice() {
+        [query] => query.clone(),
+        words => words
+            .iter()
+            .map(|word| match word.contains(' ') {
+                true => format!("\"{}\"", word),
+                false => word.clone(),
+            })
+            .collect::<Vec<_>>()
+            .join(" "),
+    }
+}
+
+/// Searches for tracks with the backend from the config. Results without an id are left out,
+/// their uri would be `spotify:track:` which players silently fail to play.
+pub async fn search(client: &Client, config: &Config, query: &str) -> Result<Vec<Track>, Error> {
+    let mut tracks = match config.search_backend()? {
+        SearchBackend::Heroku => heroku(client, query).await?,
+        #[cfg(feature = "web-api")]
+        SearchBackend::Spotify => web_api::search(client, config, query).await?,
+        #[cfg(not(feature = "web-api"))]
+        SearchBackend::Spotify => return Err(Error::WithoutWebApi),
+    };
+    drop_without_id(&mut tracks);
+    Ok(tracks)
+}
+
+/// Leaves out the tracks without an id, which can't be played
+fn drop_without_id(tracks: &mut Vec<Track>) {
+    tracks.retain(|track| !track.id.trim().is_empty());
+}
+
+async fn heroku(client: &Client, query: &str) -> Result<Vec<Track>, Error> {
+    let url = format!(
+        "https://spotify-search-api-test.herokuapp.com/search/tracks?track={}",
+        query.replace(' ', "%20")
+    );
+    let res: Response = http::get(client, &url).await?.json().await?;
+    Ok(res.tracks.items)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(artists: &[&str]) -> Track {
+        Track {
+            name: "Song".to_string(),
+            id: "id".to_string(),
+            artists: artists
+                .iter()
+                .map(|name| Artist {
+                    name: name.to_string(),
+                })
+                .collect(),
+            album: Album {
+                name: "Album".to_string(),
+            },
+            duration_ms: None,
+            popularity: None,
+        }
+    }
+
+    fn capped(max: usize) -> DisplayOptions {
+        let config = Config {
+            max_artists: Some(max),
+            ..Config::default()
+        };
+        DisplayOptions::from_config(&config).unwrap()
+    }
+
+    #[test]
+    fn no_artists() {
+        assert_eq!(track(&[]).to_string(), "Song on Album");
+    }
+
+    #[test]
+    fn one_artist() {
+        assert_eq!(track(&["A"]).to_string(), "Song by A on Album");
+    }
+
+    #[test]
+    fn two_artists() {
+        assert_eq!(track(&["A", "B"]).to_string(), "Song by A and B on Album");
+    }
+
+    #[test]
+    fn three_artists() {
+        assert_eq!(
+            track(&["A", "B", "C"]).to_string(),
+            "Song by A, B and C on Album"
+        );
+    }
+
+    #[test]
+    fn hidden_artists() {
+        let track = track(&["A", "B", "C", "D"]);
+        assert_eq!(
+            track.display(&capped(2)).to_string(),
+            "Song by A, B +2 more on Album"
+        );
+        assert_eq!(
+            track.display(&capped(1)).to_string(),
+            "Song by A +3 more on Album"
+        );
+        // Nothing is hidden when there are as many as are shown
+        assert_eq!(
+            track.display(&capped(4)).to_string(),
+            "Song by A, B, C and D on Album"
+        );
+    }
+
+    #[test]
+    fn results_without_an_id_are_left_out() {
+        let mut tracks = vec![track(&["A"]), track(&["B"]), track(&["C"])];
+        tracks[0].id = String::new();
+        tracks[2].id = "  ".to_string();
+        drop_without_id(&mut tracks);
+        assert_eq!(tracks.len(), 1);
+        assert_eq!(tracks[0].artists[0].name, "B");
+    }
+
+    #[test]
+    fn full_payload() {
+        let track: Track = serde_json::from_str(
+            r#"{
+                "name": "Song",
+                "id": "id",
+                "artists": [{ "name": "A" }, { "name": "B" }],
+                "album": { "name": "Album" },
+                "duration_ms": 180000,
+                "popularity": 70,
+                "explicit": false
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(track.to_string(), "Song by A and B on Album");
+        assert_eq!(track.duration(), Some(Duration::from_secs(180)));
+        assert_eq!(track.popularity, Some(70));
+    }
+
+    #[test]
+    fn payload_without_album() {
+        let track: Track =
+            serde_json::from_str(r#"{ "name": "Song", "id": "id", "artists": [{ "name": "A" }] }"#)
+                .unwrap();
+        assert_eq!(track.album.name, "");
+        assert_eq!(track.to_string(), "Song by A");
+    }
+
+    #[test]
+    fn payload_with_empty_artists() {
+        let track: Track =
+            serde_json::from_str(r#"{ "name": "Song", "id": "id", "artists": [], "album": {} }"#)
+                .unwrap();
+        assert!(track.artists.is_empty());
+        assert_eq!(track.to_string(), "Song");
+    }
+
+    #[test]
+    fn payload_without_duration_or_popularity() {
+        let track: Track = serde_json::from_str(r#"{ "id": "id" }"#).unwrap();
+        assert_eq!(track.name, "");
+        assert_eq!(track.duration(), None);
+        assert_eq!(track.popularity, None);
+        assert_eq!(track.uri(), "spotify:track:id");
+    }
+
+    #[test]
+    fn payload_without_an_id_fails() {
+        assert!(serde_json::from_str::<Track>(r#"{ "name": "Song" }"#).is_err());
+    }
+
+    #[test]
+    fn response_without_items() {
+        let response: Response = serde_json::from_str(r#"{ "tracks": {} }"#).unwrap();
+        assert!(response.tracks.items.is_empty());
+        let response: Response =
+            serde_json::from_str(r#"{ "tracks": { "items": [{ "id": "a" }, { "id": "b" }] } }"#)
+                .unwrap();
+        assert_eq!(response.tracks.items.len(), 2);
+    }
+
+    #[test]
+    fn no_album() {
+        let mut track = track(&["A"]);
+        track.album.name.clear();
+        assert_eq!(track.to_string(), "Song by A");
+    }
+
+    #[test]
+    fn sorting() {
+        // (name, popularity, duration in seconds)
+        let tracks = [
+            ("a", Some(50), Some(200)),
+            ("b", None, Some(100)),
+            ("c", Some(90), None),
+            ("d", Some(50), Some(300)),
+            ("e", None, None),
+        ];
+        let tracks = tracks
+            .iter()
+            .map(|&(name, popularity, secs)| Track {
+                name: name.to_string(),
+                popularity,
+                duration_ms: secs.map(|secs: u64| secs * 1000),
+                ..track(&[])
+            })
+            .collect::<Vec<_>>();
+        // Ties and missing values keep the order they had
+        let cases = [
+            (SortOrder::Relevance, "abcde"),
+            (SortOrder::Popularity, "cadbe"),
+            (SortOrder::Duration, "badce"),
+        ];
+        for (order, names) in cases {
+            let mut tracks = tracks.clone();
+            sort(&mut tracks, order);
+            let sorted = tracks.iter().map(|t| t.name.as_str()).collect::<String>();
+            assert_eq!(sorted, names, "{:?}", order);
+        }
+    }
+
+    fn query(words: &[&str]) -> String {
+        join_query(
+            &words
+                .iter()
+                .map(|word| word.to_string())
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    #[test]
+    fn queries() {
+        assert_eq!(query(&["get lucky"]), "get lucky");
+        assert_eq!(query(&["get", "lucky"]), "get lucky");
+        assert_eq!(
+            query(&["get lucky", "daft", "punk"]),
+            "\"get lucky\" daft punk"
+        );
+    }
+
+    #[test]
+    fn queries_without_redundant_whitespace() {
+        assert_eq!(query(&["  get \t lucky  "]), "get lucky");
+        assert_eq!(query(&["get", "", "  ", "lucky "]), "get lucky");
+        assert_eq!(query(&["", "get  lucky"]), "get lucky");
+        assert_eq!(query(&["get  lucky", " daft"]), "\"get lucky\" daft");
+        assert_eq!(query(&["", " "]), "");
+        assert_eq!(query(&[]), "");
+    }
+}
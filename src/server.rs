@@ -0,0 +1,131 @@
+use std::{convert::Infallible, net::SocketAddr, sync::Arc};
+
+use hyper::{
+    header::ORIGIN,
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use serde_json::{json, Value};
+
+use crate::{settle, state::state, Context, Control, Error, PlayerProxy};
+
+/// Serves the JSON API of `http` until ctrl-c is pressed
+pub async fn serve(
+    proxy: PlayerProxy<'static>,
+    ctx: Arc<Context>,
+    addr: SocketAddr,
+) -> Result<(), Error> {
+    let make = make_service_fn(move |_| {
+        let (proxy, ctx) = (proxy.clone(), ctx.clone());
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                let (proxy, ctx) = (proxy.clone(), ctx.clone());
+                async move { Ok::<_, Infallible>(handle(&proxy, &ctx, addr, req).await) }
+            }))
+        }
+    });
+    let server = Server::try_bind(&addr)?.serve(make);
+    eprintln!("Listening on http://{}", addr);
+    server
+        .with_graceful_shutdown(async {
+            let _ = tokio::signal::ctrl_c().await;
+        })
+        .await?;
+    Ok(())
+}
+
+async fn handle(
+    proxy: &PlayerProxy<'_>,
+    ctx: &Context,
+    addr: SocketAddr,
+    req: Request<Body>,
+) -> Response<Body> {
+    // Browsers send a simple POST from any page without asking first, but they do say which page
+    // it's from. Remotes that aren't web pages don't send an Origin at all.
+    if let Some(origin) = req.headers().get(ORIGIN) {
+        if !own_origin(origin.to_str().unwrap_or_default(), addr) {
+            return respond(
+                StatusCode::FORBIDDEN,
+                "requests from other web pages aren't allowed".into(),
+            );
+        }
+    }
+    // Everything that changes something is a POST, so a link or a prefetch can't skip songs
+    let result = match (req.method(), req.uri().path()) {
+        (&Method::GET, "/status") => status(proxy, ctx).await,
+        (&Method::GET, "/nowplaying") => ctx.metadata(proxy).await.map(|m| json!(m)),
+        (&Method::POST, "/next") => control(proxy, ctx, Control::Next(1)).await,
+        (&Method::POST, "/prev") => control(proxy, ctx, Control::Previous(1)).await,
+        (&Method::POST, "/playpause") => control(proxy, ctx, Control::PlayPause).await,
+        (_, "/status" | "/nowplaying" | "/next" | "/prev" | "/playpause") => {
+            return respond(StatusCode::METHOD_NOT_ALLOWED, "method not allowed".into())
+        }
+        _ => return respond(StatusCode::NOT_FOUND, "not found".into()),
+    };
+    match result {
+        Ok(body) => respond(StatusCode::OK, body),
+        Err(e) => respond(StatusCode::BAD_GATEWAY, e.to_string().into()),
+    }
+}
+
+/// Whether `origin` is the server itself, like a page it would serve. Only the address it listens
+/// on counts, a name that points there could be anyone's.
+fn own_origin(origin: &str, addr: SocketAddr) -> bool {
+    let own = format!("http://{}", addr);
+    let localhost = format!("http://localhost:{}", addr.port());
+    origin == own || (addr.ip().is_loopback() && origin == localhost)
+}
+
+/// Responds with the body as JSON, strings are errors and are sent as `{"error": "..."}`
+fn respond(status: StatusCode, body: Value) -> Response<Body> {
+    let body = match body {
+        Value::String(error) => json!({ "error": error }),
+        body => body,
+    };
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+/// The state of the player, with times in microseconds like the rest of MPRIS
+async fn status(proxy: &PlayerProxy<'_>, ctx: &Context) -> Result<Value, Error> {
+    let state = ctx.call(state(proxy)).await?;
+    Ok(json!({
+        "status": state.status,
+        "metadata": state.metadata,
+        "position": state.position.map(|p| p.as_micros() as u64),
+        "length": state.length.map(|l| l.as_micros() as u64),
+        "volume": state.volume,
+        "shuffle": state.shuffle,
+        "loop_status": state.loop_status,
+    }))
+}
+
+async fn control(proxy: &PlayerProxy<'_>, ctx: &Context, control: Control) -> Result<Value, Error> {
+    let status = settle(proxy, ctx, control).await?;
+    Ok(json!({ "status": status }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn origins() {
+        let local: SocketAddr = "127.0.0.1:8080".parse().unwrap();
+        assert!(own_origin("http://127.0.0.1:8080", local));
+        assert!(own_origin("http://localhost:8080", local));
+        assert!(!own_origin("http://localhost:9000", local));
+        assert!(!own_origin("https://example.com", local));
+        assert!(!own_origin("http://example.com:8080", local));
+        assert!(!own_origin("null", local));
+        assert!(!own_origin("", local));
+
+        let any: SocketAddr = "0.0.0.0:8080".parse().unwrap();
+        assert!(!own_origin("http://localhost:8080", any));
+        let v6: SocketAddr = "[::1]:8080".parse().unwrap();
+        assert!(own_origin("http://[::1]:8080", v6));
+    }
+}
@@ -0,0 +1,213 @@
+use std::{collections::HashMap, time::Duration};
+
+use zbus::{fdo, names::InterfaceName, zvariant::OwnedValue, CacheProperties};
+
+use crate::{as_volume, metadata, volume, Error, Metadata, PlayerProxy};
+
+const PLAYER_INTERFACE: &str = "org.mpris.MediaPlayer2.Player";
+
+/// Everything about the player that `info` shows, fetched at once by [`state`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlayerState {
+    pub status: String,
+    /// Missing when nothing is playing, or the player reports metadata that can't be read
+    pub metadata: Option<Metadata>,
+    /// The properties below are optional in MPRIS, they're missing when the player doesn't have them
+    pub position: Option<Duration>,
+    pub length: Option<Duration>,
+    /// Between 0.0 and 1.0
+    pub volume: Option<f64>,
+    pub shuffle: Option<bool>,
+    pub loop_status: Option<String>,
+}
+
+/// Converts the microseconds MPRIS uses for times, negative times are treated as 0
+fn micros(us: i64) -> Duration {
+    Duration::from_micros(us.max(0) as u64)
+}
+
+/// Reads the state of the player. Only the playback status is required, the rest is left out
+/// when it can't be read.
+pub async fn state(proxy: &PlayerProxy<'_>) -> Result<PlayerState, Error> {
+    // Getting all properties at once is a single round-trip, but not every player answers it
+    if let Ok(Some(state)) = get_all(proxy).await {
+        return Ok(state);
+    }
+    by_property(proxy).await
+}
+
+/// Talks to the properties of the player directly, so nothing comes from the cache of `proxy`
+async fn properties(proxy: &PlayerProxy<'_>) -> zbus::Result<fdo::PropertiesProxy<'static>> {
+    let inner = proxy.inner();
+    fdo::PropertiesProxy::builder(inner.connection())
+        .destination(inner.destination().to_owned())?
+        .path(inner.path().to_owned())?
+        .cache_properties(CacheProperties::No)
+        .build()
+        .await
+}
+
+/// Asks the player for a property right now, instead of using the value `proxy` has cached
+pub async fn fresh(proxy: &PlayerProxy<'_>, name: &str) -> Result<OwnedValue, Error> {
+    let value = properties(proxy)
+        .await?
+        .get(
+            InterfaceName::from_static_str_unchecked(PLAYER_INTERFACE),
+            name,
+        )
+        .await?;
+    Ok(value)
+}
+
+/// Reads the state with a single GetAll call, `None` when the player left out the playback status
+async fn get_all(proxy: &PlayerProxy<'_>) -> zbus::Result<Option<PlayerState>> {
+    let all = properties(proxy)
+        .await?
+        .get_all(InterfaceName::from_static_str_unchecked(PLAYER_INTERFACE))
+        .await?;
+
+    // Players don't have to include properties without change signals, like the position
+    let has_position = all.contains_key("Position");
+    let mut state = match from_properties(all) {
+        Some(state) => state,
+        None => return Ok(None),
+    };
+    if !has_position {
+        state.position = proxy.position().await.ok().map(micros);
+    }
+    Ok(Some(state))
+}
+
+/// Builds the state from the properties GetAll answered with, `None` without a playback status.
+/// Properties that are missing or can't be read are left out.
+fn from_properties(mut all: HashMap<String, OwnedValue>) -> Option<PlayerState> {
+    let status = String::try_from(all.remove("PlaybackStatus")?).ok()?;
+    let metadata = all
+        .remove("Metadata")
+        .and_then(|m| Metadata::try_from(m).ok());
+    Some(PlayerState {
+        status,
+        length: metadata.as_ref().and_then(|m| m.length).map(micros),
+        metadata,
+        position: all
+            .remove("Position")
+            .and_then(|v| i64::try_from(v).ok())
+            .map(micros),
+        volume: all.get("Volume").and_then(|v| as_volume(v)),
+        shuffle: all.remove("Shuffle").and_then(|v| bool::try_from(v).ok()),
+        loop_status: all
+            .remove("LoopStatus")
+            .and_then(|v| String::try_from(v).ok()),
+    })
+}
+
+/// Reads the state one property at a time
+async fn by_property(proxy: &PlayerProxy<'_>) -> Result<PlayerState, Error> {
+    // The reads are sent together instead of waiting for each answer in turn. Most of them are
+    // answered from the property cache of the proxy anyway, only the position is always asked for.
+    let (status, metadata, position, volume, shuffle, loop_status) = tokio::join!(
+        proxy.playback_status(),
+        metadata(proxy),
+        proxy.position(),
+        volume(proxy),
+        proxy.shuffle(),
+        proxy.loop_status(),
+    );
+    let metadata = metadata.ok();
+    Ok(PlayerState {
+        status: status?,
+        length: metadata.as_ref().and_then(|m| m.length).map(micros),
+        metadata,
+        position: position.ok().map(micros),
+        volume: volume.ok(),
+        shuffle: shuffle.ok(),
+        loop_status: loop_status.ok(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use zbus::zvariant::Value;
+
+    use super::*;
+
+    fn properties(entries: Vec<(&str, Value<'_>)>) -> HashMap<String, OwnedValue> {
+        entries
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value.into()))
+            .collect()
+    }
+
+    fn metadata() -> Value<'static> {
+        let mut map = HashMap::new();
+        map.insert("xesam:title".to_string(), Value::new("Song"));
+        map.insert("xesam:artist".to_string(), Value::new(vec!["Artist"]));
+        map.insert("mpris:length".to_string(), Value::new(180_000_000i64));
+        Value::Dict(map.into())
+    }
+
+    #[test]
+    fn all_properties() {
+        let state = from_properties(properties(vec![
+            ("PlaybackStatus", Value::new("Playing")),
+            ("Metadata", metadata()),
+            ("Position", Value::new(61_500_000i64)),
+            ("Volume", Value::new(0.5)),
+            ("Shuffle", Value::new(true)),
+            ("LoopStatus", Value::new("Playlist")),
+        ]))
+        .unwrap();
+        assert_eq!(state.status, "Playing");
+        assert_eq!(state.metadata.unwrap().title, "Song");
+        assert_eq!(state.length, Some(Duration::from_secs(180)));
+        assert_eq!(state.position, Some(Duration::from_millis(61_500)));
+        assert_eq!(state.volume, Some(0.5));
+        assert_eq!(state.shuffle, Some(true));
+        assert_eq!(state.loop_status.as_deref(), Some("Playlist"));
+    }
+
+    #[test]
+    fn only_the_status_is_required() {
+        let state = from_properties(properties(vec![("PlaybackStatus", Value::new("Paused"))]));
+        assert_eq!(
+            state,
+            Some(PlayerState {
+                status: "Paused".to_string(),
+                metadata: None,
+                position: None,
+                length: None,
+                volume: None,
+                shuffle: None,
+                loop_status: None,
+            })
+        );
+        assert_eq!(
+            from_properties(properties(vec![("Volume", Value::new(0.5))])),
+            None
+        );
+        assert_eq!(
+            from_properties(properties(vec![("PlaybackStatus", Value::new(1i32))])),
+            None
+        );
+    }
+
+    #[test]
+    fn unreadable_properties_are_left_out() {
+        let state = from_properties(properties(vec![
+            ("PlaybackStatus", Value::new("Stopped")),
+            ("Metadata", Value::new("not a dict")),
+            ("Position", Value::new(-5i64)),
+            ("Volume", Value::new(40u32)),
+            ("Shuffle", Value::new("yes")),
+            ("LoopStatus", Value::new(2i32)),
+        ]))
+        .unwrap();
+        assert_eq!(state.metadata, None);
+        assert_eq!(state.length, None);
+        // Negative times are treated as 0, and integer volumes are percentages
+        assert_eq!(state.position, Some(Duration::ZERO));
+        assert_eq!(state.volume, Some(0.4));
+        assert_eq!(state.shuffle, None);
+        assert_eq!(state.loop_status, None);
+    }
+}
@@ -0,0 +1,146 @@
+use reqwest::{Client, Url};
+use serde::Deserialize;
+use serde_json::json;
+
+#[cfg(feature = "search")]
+use crate::search::{Response, Track};
+use crate::{config::Config, http, Error};
+
+const TOKEN_URL: &str = "https://accounts.spotify.com/api/token";
+const API_URL: &str = "https://api.spotify.com/v1";
+const AUTHORIZE_URL: &str = "https://accounts.spotify.com/authorize";
+/// Has to be added to the app on the dashboard, nothing has to listen on it
+pub const REDIRECT_URI: &str = "http://127.0.0.1:8888/callback";
+/// Everything the commands that act for the user need
+const SCOPES: &str =
+    "user-read-playback-state user-modify-playback-state user-library-read user-library-modify";
+
+#[derive(Deserialize)]
+struct Token {
+    access_token: String,
+    /// Only sent when authorizing
+    #[serde(default)]
+    refresh_token: Option<String>,
+}
+
+/// A Spotify Connect device
+#[derive(Deserialize, Debug)]
+pub struct Device {
+    /// Missing for devices that can't be controlled with the Web API
+    pub id: Option<String>,
+    pub name: String,
+}
+
+#[derive(Deserialize)]
+struct Devices {
+    devices: Vec<Device>,
+}
+
+/// Gets an access token with the client credentials flow, which is enough for searching
+#[cfg(feature = "search")]
+async fn token(client: &Client, config: &Config) -> Result<String, Error> {
+    let (id, secret) = config.credentials().ok_or(Error::MissingCredentials)?;
+    let request = client
+        .post(TOKEN_URL)
+        .basic_auth(id, Some(secret))
+        .form(&[("grant_type", "client_credentials")]);
+    let token: Token = http::send(request).await?.json().await?;
+    Ok(token.access_token)
+}
+
+/// Gets an access token for the user with the `refresh_token` from the config, which is needed
+/// for anything that isn't searching
+async fn user_token(client: &Client, config: &Config) -> Result<String, Error> {
+    let (id, secret) = config.credentials().ok_or(Error::MissingCredentials)?;
+    let refresh_token = config.refresh_token().ok_or(Error::MissingRefreshToken)?;
+    let request = client.post(TOKEN_URL).basic_auth(id, Some(secret)).form(&[
+        ("grant_type", "refresh_token"),
+        ("refresh_token", refresh_token),
+    ]);
+    let token: Token = http::send(request).await?.json().await?;
+    Ok(token.access_token)
+}
+
+/// The page the user has to visit to let `auth` act for them
+pub fn authorize_url(config: &Config) -> Result<Url, Error> {
+    let (id, _) = config.credentials().ok_or(Error::MissingCredentials)?;
+    let params = [
+        ("client_id", id),
+        ("response_type", "code"),
+        ("redirect_uri", REDIRECT_URI),
+        ("scope", SCOPES),
+    ];
+    Ok(Url::parse_with_params(AUTHORIZE_URL, params).unwrap())
+}
+
+/// Trades the code Spotify redirected to for a refresh token
+pub async fn refresh_token(client: &Client, config: &Config, code: &str) -> Result<String, Error> {
+    let (id, secret) = config.credentials().ok_or(Error::MissingCredentials)?;
+    let request = client.post(TOKEN_URL).basic_auth(id, Some(secret)).form(&[
+        ("grant_type", "authorization_code"),
+        ("code", code),
+        ("redirect_uri", REDIRECT_URI),
+    ]);
+    let token: Token = http::send(request).await?.json().await?;
+    token.refresh_token.ok_or(Error::MissingRefreshToken)
+}
+
+/// The devices that are online right now
+pub async fn devices(client: &Client, config: &Config) -> Result<Vec<Device>, Error> {
+    let token = user_token(client, config).await?;
+    let request = client
+        .get(format!("{}/me/player/devices", API_URL))
+        .bearer_auth(token);
+    let res: Devices = http::send(request).await?.json().await?;
+    Ok(res.devices)
+}
+
+/// Moves playback to the device and starts playing there
+pub async fn play_on(client: &Client, config: &Config, device_id: &str) -> Result<(), Error> {
+    let token = user_token(client, config).await?;
+    let request = client
+        .put(format!("{}/me/player", API_URL))
+        .bearer_auth(token)
+        .json(&json!({ "device_ids": [device_id], "play": true }));
+    http::send(request).await?;
+    Ok(())
+}
+
+/// Whether the track is saved in the user's library
+pub async fn saved(client: &Client, config: &Config, id: &str) -> Result<bool, Error> {
+    let token = user_token(client, config).await?;
+    let request = client
+        .get(format!("{}/me/tracks/contains", API_URL))
+        .bearer_auth(token)
+        .query(&[("ids", id)]);
+    let res: Vec<bool> = http::send(request).await?.json().await?;
+    Ok(res.first().copied().unwrap_or(false))
+}
+
+/// Saves the track in the user's library, or removes it from there when `saved` is false
+pub async fn set_saved(
+    client: &Client,
+    config: &Config,
+    id: &str,
+    saved: bool,
+) -> Result<(), Error> {
+    let token = user_token(client, config).await?;
+    let url = format!("{}/me/tracks", API_URL);
+    let request = match saved {
+        true => client.put(url),
+        false => client.delete(url),
+    };
+    http::send(request.bearer_auth(token).query(&[("ids", id)])).await?;
+    Ok(())
+}
+
+#[cfg(feature = "search")]
+pub async fn search(client: &Client, config: &Config, query: &str) -> Result<Vec<Track>, Error> {
+    let token = token(client, config).await?;
+    let request = client
+        .get(format!("{}/search", API_URL))
+        .bearer_auth(token)
+        .query(&[("q", query), ("type", "track"), ("limit", "50")]);
+    let res: Response = http::send(request).await?.json().await?;
+    Ok(res.tracks.items)
+}